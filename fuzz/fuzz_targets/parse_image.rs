@@ -0,0 +1,30 @@
+#![no_main]
+
+use fat_rs::fat32::{EntryType, FAT32};
+use libfuzzer_sys::fuzz_target;
+use std::io::{self, Read, Seek};
+
+fuzz_target!(|data: &[u8]| {
+    // any input must give an error rather than a panic,
+    // from the boot record down to the file contents
+    let mut fs = match FAT32::from_reader(io::Cursor::new(data.to_vec())) {
+        Ok(fs) => fs,
+        Err(_) => return,
+    };
+    let _ = fs.free_space();
+    let _ = fs.check_integrity();
+    let root = fs.root_directory();
+    let items: Vec<_> = fs.walk(root).take(64).filter_map(Result::ok).collect();
+    for item in items {
+        let file = match item.entry.entry_type() {
+            EntryType::File(file) => file,
+            EntryType::Dir(_) => continue,
+        };
+        let _ = fs.read_file(file);
+        let _ = fs.read_slack_space(file);
+        let _ = fs.read_file_at(file, 100, &mut [0u8; 600]);
+        let mut reader = fs.open_file(file);
+        let _ = reader.seek(io::SeekFrom::End(-10));
+        let _ = reader.read(&mut [0u8; 64]);
+    }
+});
@@ -0,0 +1,201 @@
+use crate::error::FatError;
+use crate::fat32::{
+    check_chain_link, BootSector, Directory, DirectoryEntry, ExtendedFlags, File, END_OF_CHAIN,
+};
+use std::collections::BTreeSet;
+use std::{cmp, io, path};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+// read only FAT32 volume on a tokio file, the boot record
+// and entries are parsed as FAT32 does; there is no cache
+// of the FAT sectors
+#[derive(Debug)]
+pub struct AsyncFAT32 {
+    file: tokio::fs::File,
+    boot: BootSector,
+}
+
+impl AsyncFAT32 {
+    pub async fn new(path: &path::Path) -> Result<AsyncFAT32, FatError> {
+        let mut file = tokio::fs::File::open(path).await?;
+        let mut sector = [0u8; 90];
+        file.read_exact(&mut sector).await?;
+        let boot = BootSector::parse(&sector)?;
+        Ok(AsyncFAT32 { file, boot })
+    }
+
+    pub fn boot_sector(&self) -> &BootSector {
+        &self.boot
+    }
+
+    pub fn root_directory(&self) -> Directory {
+        Directory {
+            cluster: self.boot.root_cluster,
+        }
+    }
+
+    pub async fn read_directory(
+        &mut self,
+        dir: Directory,
+    ) -> Result<Vec<DirectoryEntry>, FatError> {
+        // same entries as FAT32::read_directory: no LFN,
+        // deleted or label entries
+        let cluster_bytes = self.cluster_size_bytes() as u64;
+        let mut entries = Vec::new();
+        let mut long_name = None;
+        for cluster in self.chain(dir.cluster).await? {
+            let start = self.cluster_offset(cluster);
+            let mut data = vec![0u8; cluster_bytes as usize];
+            self.read_at(start, &mut data).await?;
+
+            for (i, chunk) in data.chunks_exact(32).enumerate() {
+                let mut raw = [0u8; 32];
+                raw.copy_from_slice(chunk);
+                if raw[0] == 0 {
+                    // end marker
+                    return Ok(entries);
+                }
+                let offset = start + (i << 5) as u64;
+                if let Some(entry) = DirectoryEntry::parse(&raw, offset, &mut long_name, false) {
+                    if !entry.is_volume_label() {
+                        entries.push(entry);
+                    }
+                }
+            }
+        }
+        Ok(entries)
+    }
+
+    pub async fn read_file(&mut self, file: File) -> Result<Vec<u8>, FatError> {
+        let mut data = Vec::with_capacity(file.size as usize);
+        let cluster_bytes = self.cluster_size_bytes() as usize;
+        for cluster in self.chain(file.cluster).await? {
+            if data.len() >= file.size as usize {
+                break;
+            }
+            let len = cmp::min(cluster_bytes, file.size as usize - data.len());
+            let start = data.len();
+            data.resize(start + len, 0);
+            self.read_at(self.cluster_offset(cluster), &mut data[start..])
+                .await?;
+        }
+        if data.len() < file.size as usize {
+            return Err(FatError::UnexpectedEof);
+        }
+        Ok(data)
+    }
+
+    async fn chain(&mut self, start: u32) -> Result<Vec<u32>, FatError> {
+        // clusters of a chain, checked as FatChain does
+        let max = self.cluster_count() + 1;
+        let mut clusters = Vec::new();
+        let mut visited = BTreeSet::new();
+        let mut cluster = start;
+        while (2..END_OF_CHAIN).contains(&cluster) {
+            if !visited.insert(cluster) {
+                return Err(FatError::CyclicFatChain(cluster));
+            }
+            if cluster > max {
+                return Err(FatError::InvalidCluster { cluster, max });
+            }
+            let next = self.fat_lookup(cluster).await?;
+            check_chain_link(cluster, next, max)?;
+            clusters.push(cluster);
+            cluster = next;
+        }
+        Ok(clusters)
+    }
+
+    async fn fat_lookup(&mut self, cluster: u32) -> Result<u32, FatError> {
+        use byteorder::{ByteOrder, LittleEndian};
+
+        // entry of the active FAT, without its 4 reserved bits
+        let flags = ExtendedFlags::from(self.boot.ext_flags);
+        let fat = if flags.mirroring_disabled {
+            flags.active_fat as u64
+        } else {
+            0
+        };
+        let fat_start = self.boot.reserved_sectors as u64 + fat * self.boot.fat_size as u64;
+        let offset = fat_start * self.boot.bytes_per_sector as u64 + ((cluster as u64) << 2);
+        let mut raw = [0u8; 4];
+        self.read_at(offset, &mut raw).await?;
+        Ok(LittleEndian::read_u32(&raw) & 0x0fffffff)
+    }
+
+    async fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        self.file.seek(io::SeekFrom::Start(offset)).await?;
+        self.file.read_exact(buf).await?;
+        Ok(())
+    }
+
+    fn cluster_size_bytes(&self) -> u32 {
+        self.boot.sectors_per_cluster as u32 * self.boot.bytes_per_sector as u32
+    }
+
+    fn data_start_sector(&self) -> u32 {
+        self.boot.reserved_sectors as u32 + self.boot.num_fats as u32 * self.boot.fat_size
+    }
+
+    fn cluster_count(&self) -> u32 {
+        self.boot
+            .total_sectors
+            .saturating_sub(self.data_start_sector())
+            / self.boot.sectors_per_cluster as u32
+    }
+
+    fn cluster_offset(&self, cluster: u32) -> u64 {
+        let sector = self.data_start_sector() as u64
+            + (cluster as u64 - 2) * self.boot.sectors_per_cluster as u64;
+        sector * self.boot.bytes_per_sector as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fat32::{EntryType, FAT32};
+    use std::fs;
+
+    #[test]
+    fn async_reads() {
+        let path = std::env::temp_dir().join("fat-rs-async.img");
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .unwrap();
+        let data: Vec<u8> = (0..5000u32).map(|i| (i % 251) as u8).collect();
+        let mut formatted = FAT32::format(file, "ASYNC", 64 << 20).unwrap();
+        let root = formatted.root_directory();
+        let dir = formatted.create_directory(root, "DIR").unwrap();
+        formatted.write_file(dir, "DATA.BIN", &data).unwrap();
+        formatted.set_volume_label("ASYNC").unwrap();
+        formatted.flush().unwrap();
+        drop(formatted);
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let mut fs = AsyncFAT32::new(&path).await.unwrap();
+            let root = fs.root_directory();
+            let entries = fs.read_directory(root).await.unwrap();
+            assert_eq!(entries.len(), 1);
+            let dir = match entries[0].entry_type() {
+                EntryType::Dir(dir) => dir,
+                EntryType::File(_) => panic!("DIR is a file"),
+            };
+            let entries = fs.read_directory(dir).await.unwrap();
+            let names: Vec<String> = entries.iter().map(|entry| entry.full_name()).collect();
+            assert_eq!(names, [".", "..", "DATA.BIN"]);
+            let file = match entries[2].entry_type() {
+                EntryType::File(file) => file,
+                EntryType::Dir(_) => panic!("DATA.BIN is a directory"),
+            };
+            assert_eq!(fs.read_file(file).await.unwrap(), data);
+        });
+    }
+}
@@ -0,0 +1,61 @@
+use bitflags::bitflags;
+
+bitflags! {
+    // attribute byte of a directory entry
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+    pub struct FileAttributes: u8 {
+        const READ_ONLY = 0x01;
+        const HIDDEN = 0x02;
+        const SYSTEM = 0x04;
+        const VOLUME_LABEL = 0x08;
+        const DIRECTORY = 0x10;
+        const ARCHIVE = 0x20;
+    }
+}
+
+// serialized form of the attributes, one named
+// boolean per bit instead of the raw byte
+#[cfg(feature = "serde")]
+#[derive(Default, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+struct AttributeFlags {
+    read_only: bool,
+    hidden: bool,
+    system: bool,
+    volume_label: bool,
+    directory: bool,
+    archive: bool,
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for FileAttributes {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        AttributeFlags {
+            read_only: self.contains(FileAttributes::READ_ONLY),
+            hidden: self.contains(FileAttributes::HIDDEN),
+            system: self.contains(FileAttributes::SYSTEM),
+            volume_label: self.contains(FileAttributes::VOLUME_LABEL),
+            directory: self.contains(FileAttributes::DIRECTORY),
+            archive: self.contains(FileAttributes::ARCHIVE),
+        }
+        .serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for FileAttributes {
+    fn deserialize<D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<FileAttributes, D::Error> {
+        // missing fields are unset bits
+        let flags = AttributeFlags::deserialize(deserializer)?;
+        let mut attributes = FileAttributes::empty();
+        attributes.set(FileAttributes::READ_ONLY, flags.read_only);
+        attributes.set(FileAttributes::HIDDEN, flags.hidden);
+        attributes.set(FileAttributes::SYSTEM, flags.system);
+        attributes.set(FileAttributes::VOLUME_LABEL, flags.volume_label);
+        attributes.set(FileAttributes::DIRECTORY, flags.directory);
+        attributes.set(FileAttributes::ARCHIVE, flags.archive);
+        Ok(attributes)
+    }
+}
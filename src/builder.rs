@@ -0,0 +1,93 @@
+use crate::error::FatError;
+use crate::fat32::FAT32;
+use crate::io;
+use alloc::{string::String, string::ToString, vec::Vec};
+
+// describes a FAT32 image to create in memory,
+// mostly to give each test the tree it needs
+#[derive(Clone, Debug)]
+pub struct ImageBuilder {
+    size_bytes: u64,
+    label: String,
+    files: Vec<(String, Vec<u8>)>,
+    dirs: Vec<String>,
+}
+
+impl ImageBuilder {
+    pub fn new(size_bytes: u64) -> ImageBuilder {
+        ImageBuilder {
+            size_bytes,
+            label: String::new(),
+            files: Vec::new(),
+            dirs: Vec::new(),
+        }
+    }
+
+    pub fn label(mut self, label: &str) -> ImageBuilder {
+        self.label = label.to_string();
+        self
+    }
+
+    pub fn add_dir(mut self, path: &str) -> ImageBuilder {
+        // parents must be added before their children
+        self.dirs.push(path.to_string());
+        self
+    }
+
+    pub fn add_file(mut self, path: &str, data: &[u8]) -> ImageBuilder {
+        self.files.push((path.to_string(), data.to_vec()));
+        self
+    }
+
+    pub fn build(&self) -> Result<Vec<u8>, FatError> {
+        // format, then directories first so
+        // the files can be placed in them
+        let mut image = Vec::new();
+        let mut fs = FAT32::format(io::Cursor::new(&mut image), &self.label, self.size_bytes)?;
+        for path in self.dirs.iter() {
+            let (parent, name) = fs.parent_directory(path)?;
+            fs.create_directory(parent, name)?;
+        }
+        for (path, data) in self.files.iter() {
+            let (parent, name) = fs.parent_directory(path)?;
+            fs.write_file(parent, name, data)?;
+        }
+        fs.flush()?;
+        drop(fs);
+        Ok(image)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fat32::EntryType;
+
+    #[test]
+    fn build_images() {
+        let image = ImageBuilder::new(33 << 20)
+            .label("TEST")
+            .add_dir("/docs")
+            .add_dir("/docs/old")
+            .add_file("/docs/hello.txt", b"hello world")
+            .add_file("/docs/old/empty.txt", b"")
+            .add_file("/top.bin", &[7u8; 5000])
+            .build()
+            .unwrap();
+        assert_eq!(image.len(), 33 << 20);
+
+        let mut fs = FAT32::from_reader(io::Cursor::new(image)).unwrap();
+        assert_eq!(fs.volume_name(), "TEST");
+        assert_eq!(fs.check_integrity().unwrap(), vec![]);
+        match fs.open_path("/docs/hello.txt").unwrap() {
+            EntryType::File(file) => assert_eq!(fs.read_file(file).unwrap(), b"hello world"),
+            EntryType::Dir(_) => panic!("hello.txt is a directory"),
+        }
+        assert_eq!(fs.stat("/docs/old/empty.txt").unwrap().size, 0);
+        assert_eq!(fs.stat("/top.bin").unwrap().size, 5000);
+
+        // parents must exist
+        let missing = ImageBuilder::new(33 << 20).add_file("/nowhere/a.txt", b"a");
+        assert!(matches!(missing.build(), Err(FatError::NotFound)));
+    }
+}
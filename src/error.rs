@@ -0,0 +1,181 @@
+use crate::io;
+#[cfg(feature = "alloc")]
+use alloc::{string::String, vec::Vec};
+use core::{error, fmt, str};
+
+// errors returned by the file system implementations
+#[derive(Debug)]
+pub enum FatError {
+    // error from the underlying image
+    Io(io::Error),
+    // unexpected boot record signature
+    InvalidSignature(u8),
+    // boot record field with an unsupported or invalid value
+    InvalidBootRecord(&'static str),
+    // a cluster chain loops back on itself at this cluster
+    CyclicFatChain(u32),
+    // a cluster chain goes through a cluster marked as bad
+    BadCluster(u32),
+    // cluster number outside of the data region
+    InvalidCluster {
+        cluster: u32,
+        max: u32,
+    },
+    // sector number outside of the volume
+    InvalidSector {
+        sector: u32,
+        max: u32,
+    },
+    // a cluster chain ends before the end of the data it holds
+    UnexpectedEof,
+    // name or label that is not valid text
+    Utf8Error(str::Utf8Error),
+    // name that can't be stored in a directory entry
+    InvalidName,
+    // host files whose names don't fit in 8.3 short names
+    #[cfg(feature = "alloc")]
+    InvalidNames(Vec<String>),
+    // an entry with the same name is already in the directory
+    AlreadyExists,
+    // not enough free clusters left
+    DiskFull,
+    // volume too small to be formatted as FAT32
+    VolumeTooSmall,
+    // no run of free clusters is long enough
+    NoContiguousSpace,
+    // a cluster of a deleted file has been allocated again
+    ClusterOverwritten(u32),
+    // file size would not fit on 32 bits
+    FileTooLarge,
+    // no entry with this name
+    NotFound,
+    // the operation only applies to files
+    IsDirectory,
+    // a path component that should be a directory is a file
+    NotADirectory,
+    // the operation can't be done on this entry
+    InvalidOperation,
+    // date or time outside of what FAT entries can hold
+    InvalidTimestamp,
+}
+
+impl fmt::Display for FatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FatError::Io(err) => write!(f, "I/O error: {}", err),
+            FatError::InvalidSignature(signature) => write!(
+                f,
+                "invalid extended boot signature: expected 0x28 or 0x29, found {:#04x}",
+                signature
+            ),
+            FatError::InvalidBootRecord(reason) => write!(f, "invalid boot record: {}", reason),
+            FatError::CyclicFatChain(cluster) => {
+                write!(f, "detected cycle in FAT chain at cluster {}", cluster)
+            }
+            FatError::BadCluster(cluster) => {
+                write!(f, "FAT chain goes through bad cluster {}", cluster)
+            }
+            FatError::InvalidCluster { cluster, max } => {
+                write!(f, "cluster {} out of range (max {})", cluster, max)
+            }
+            FatError::InvalidSector { sector, max } => {
+                write!(f, "sector {} out of range (max {})", sector, max)
+            }
+            FatError::UnexpectedEof => write!(f, "FAT chain ends before the end of the data"),
+            FatError::Utf8Error(err) => write!(f, "invalid UTF-8 text: {}", err),
+            FatError::InvalidName => write!(f, "invalid file name"),
+            #[cfg(feature = "alloc")]
+            FatError::InvalidNames(names) => {
+                write!(f, "names not valid in 8.3 format: {}", names.join(", "))
+            }
+            FatError::AlreadyExists => write!(f, "file already exists"),
+            FatError::DiskFull => write!(f, "no free cluster left on the volume"),
+            FatError::VolumeTooSmall => {
+                write!(f, "volume too small for the 65525 clusters of FAT32")
+            }
+            FatError::NoContiguousSpace => write!(f, "not enough contiguous free clusters"),
+            FatError::ClusterOverwritten(cluster) => {
+                write!(f, "cluster {} of the deleted file is in use again", cluster)
+            }
+            FatError::FileTooLarge => write!(f, "file too large"),
+            FatError::NotFound => write!(f, "no such file or directory"),
+            FatError::IsDirectory => write!(f, "is a directory"),
+            FatError::NotADirectory => write!(f, "not a directory"),
+            FatError::InvalidOperation => write!(f, "operation not supported on this entry"),
+            FatError::InvalidTimestamp => write!(f, "timestamp not representable in FAT"),
+        }
+    }
+}
+
+impl error::Error for FatError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            FatError::Io(err) => Some(err),
+            FatError::Utf8Error(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for FatError {
+    fn from(err: io::Error) -> FatError {
+        FatError::Io(err)
+    }
+}
+
+impl From<str::Utf8Error> for FatError {
+    fn from(err: str::Utf8Error) -> FatError {
+        FatError::Utf8Error(err)
+    }
+}
+
+impl From<FatError> for io::Error {
+    fn from(err: FatError) -> io::Error {
+        // used where an io::Error is required, e.g. in the
+        // Read implementations; without std the message
+        // can't be kept
+        match err {
+            FatError::Io(err) => err,
+            FatError::UnexpectedEof => io::Error::from(io::ErrorKind::UnexpectedEof),
+            #[cfg(feature = "std")]
+            err => io::Error::new(io::ErrorKind::InvalidData, err.to_string()),
+            #[cfg(not(feature = "std"))]
+            _ => io::Error::new(io::ErrorKind::InvalidData, "invalid FAT volume"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error;
+
+    #[test]
+    fn error_messages() {
+        let err = FatError::InvalidCluster {
+            cluster: 70000,
+            max: 65535,
+        };
+        assert_eq!(err.to_string(), "cluster 70000 out of range (max 65535)");
+        assert_eq!(
+            FatError::CyclicFatChain(12).to_string(),
+            "detected cycle in FAT chain at cluster 12"
+        );
+        assert_eq!(
+            FatError::InvalidSignature(0x12).to_string(),
+            "invalid extended boot signature: expected 0x28 or 0x29, found 0x12"
+        );
+        assert_eq!(FatError::NotFound.to_string(), "no such file or directory");
+        assert!(FatError::NotFound.source().is_none());
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn io_errors() {
+        let err = FatError::from(io::Error::from(io::ErrorKind::PermissionDenied));
+        assert!(err.to_string().starts_with("I/O error: "));
+        assert!(err.source().is_some());
+        let err = io::Error::from(FatError::AlreadyExists);
+        assert_eq!(err.to_string(), "file already exists");
+    }
+}
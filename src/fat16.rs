@@ -1,10 +1,17 @@
-use byteorder::ReadBytesExt;
+use crate::error::FatError;
+use crate::fat32::{decode_short_name, short_full_name};
+use crate::io;
+use crate::io::Read;
+use crate::io::Seek;
+use alloc::borrow::Cow;
+use alloc::collections::BTreeSet;
+use alloc::{string::String, vec, vec::Vec};
+use byteorder::ByteOrder;
+use core::str;
+#[cfg(feature = "std")]
 use std::fs::File;
-use std::io;
-use std::io::Read;
-use std::io::Seek;
+#[cfg(feature = "std")]
 use std::path::Path;
-use std::str;
 
 // BIOS Parameter Block,
 // basic info about the volume
@@ -36,32 +43,45 @@ struct BootRecord {
 }
 
 impl BootRecord {
-    fn parse(file: &mut File) -> io::Result<BootRecord> {
-        use byteorder::LittleEndian;
+    fn parse<B: Read + Seek>(file: &mut B) -> Result<BootRecord, FatError> {
+        use byteorder::{ByteOrder, LittleEndian};
+        // boot record fields, up to the volume label
+        let mut data = [0u8; 54];
+        file.seek(io::SeekFrom::Start(0))?;
+        file.read_exact(&mut data)?;
+
         // skip boot jump and OEM identifier
-        file.seek(io::SeekFrom::Start(11))?;
-
-        let sector_size = file.read_u16::<LittleEndian>()?;
-        let cluster_size = file.read_u8()?;
-        let reserved_sectors = file.read_u16::<LittleEndian>()?;
-        let fat_count = file.read_u8()?;
-        let root_entries = file.read_u16::<LittleEndian>()?;
-        let sector_count = file.read_u16::<LittleEndian>()?;
+        let sector_size = LittleEndian::read_u16(&data[11..]);
+        let cluster_size = data[13];
+        let reserved_sectors = LittleEndian::read_u16(&data[14..]);
+        let fat_count = data[16];
+        let root_entries = LittleEndian::read_u16(&data[17..]);
+        let sector_count = LittleEndian::read_u16(&data[19..]);
         // skip media parameter type
-        let _ = file.read_u8()?;
-        let fat_size = file.read_u16::<LittleEndian>()?;
+        let fat_size = LittleEndian::read_u16(&data[22..]);
         // skip drive geometry info
-        let _ = file.read_u64::<LittleEndian>()?;
-        let large_sector_count = file.read_u32::<LittleEndian>()?;
+        let large_sector_count = LittleEndian::read_u32(&data[32..]);
         // extended boot record
         // skip drive number
-        let _ = file.read_u8()?;
-        let _flags = file.read_u8()?;
-        let signature = file.read_u8()?;
-        assert!(signature == 0x28 || signature == 0x29);
+        let _flags = data[37];
+        let signature = data[38];
+        if signature != 0x28 && signature != 0x29 {
+            return Err(FatError::InvalidBootRecord(
+                "invalid extended boot signature",
+            ));
+        }
+        // skip volume serial number
         let mut label = [0u8; 11];
-        let _ = file.read_u32::<LittleEndian>()?;
-        file.read_exact(&mut label)?;
+        label.copy_from_slice(&data[43..54]);
+        // volume_name returns it as is
+        str::from_utf8(&label)?;
+        if fat_size == 0 {
+            // FAT32 keeps its FAT size in another field
+            return Err(FatError::InvalidBootRecord("FAT32 boot record"));
+        }
+        if sector_size == 0 || cluster_size == 0 {
+            return Err(FatError::InvalidBootRecord("null sector or cluster size"));
+        }
 
         Ok(BootRecord {
             sector_size,
@@ -78,17 +98,68 @@ impl BootRecord {
     }
 }
 
-pub struct FileSystem {
-    file: std::fs::File,
+// FAT variant, given by the number of clusters
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FatType {
+    // 12 bits FAT entries, floppy disks
+    Fat12,
+    // 16 bits FAT entries
+    Fat16,
+}
+
+impl FatType {
+    fn from_cluster_count(count: u32) -> FatType {
+        // limit from the FAT specification
+        if count < 4085 {
+            FatType::Fat12
+        } else {
+            FatType::Fat16
+        }
+    }
+}
+
+// image of the volumes opened from a path
+#[cfg(feature = "std")]
+type DefaultBackend = File;
+#[cfg(not(feature = "std"))]
+type DefaultBackend = io::Cursor<Vec<u8>>;
+
+pub struct FileSystem<B = DefaultBackend> {
+    file: B,
     br: BootRecord,
+    fat_type: FatType,
 }
 
-impl FileSystem {
-    pub fn new(path: &Path) -> io::Result<FileSystem> {
-        let mut file = File::open(path)?;
+#[cfg(feature = "std")]
+impl FileSystem<File> {
+    pub fn new(path: &Path) -> Result<FileSystem, FatError> {
+        FileSystem::from_reader(File::open(path)?)
+    }
+}
+
+impl<B: Read + Seek> FileSystem<B> {
+    pub fn from_reader(mut file: B) -> Result<FileSystem<B>, FatError> {
         let br = BootRecord::parse(&mut file)?;
 
-        Ok(FileSystem { file, br })
+        let mut fs = FileSystem {
+            file,
+            br,
+            fat_type: FatType::Fat16,
+        };
+        fs.fat_type = FatType::from_cluster_count(fs.cluster_count());
+        Ok(fs)
+    }
+
+    pub fn fat_type(&self) -> FatType {
+        self.fat_type
+    }
+
+    pub fn cluster_count(&self) -> u32 {
+        // number of clusters in the data region
+        let data = self
+            .sectors_count()
+            .saturating_sub(self.data_start_sector());
+        data / self.br.cluster_size as u32
     }
 
     pub fn sectors_count(&self) -> u32 {
@@ -100,7 +171,8 @@ impl FileSystem {
     }
 
     pub fn volume_size(&self) -> u32 {
-        self.sectors_count() * self.br.sector_size as u32
+        self.sectors_count()
+            .saturating_mul(self.br.sector_size as u32)
     }
 
     pub fn volume_name(&self) -> &str {
@@ -123,72 +195,145 @@ impl FileSystem {
 
     fn data_start_sector(&self) -> u32 {
         // max size of root dir, in bytes
-        let root_size = self.br.root_entries << 5;
-        self.root_start_sector() + (root_size / self.br.sector_size) as u32
+        // (computed on 32 bits, may not fit a u16 with large sectors)
+        let root_size = (self.br.root_entries as u32) << 5;
+        self.root_start_sector() + root_size.div_ceil(self.br.sector_size as u32)
     }
 
-    fn cluster_start(&self, cluster: u16) -> u32 {
-        self.data_start_sector() + (cluster - 2) as u32 * self.br.cluster_size as u32
+    fn cluster_start(&self, cluster: u16) -> Result<u64, FatError> {
+        // byte offset of a cluster of the data region
+        let max = self.cluster_count() + 1;
+        if cluster < 2 || cluster as u32 > max {
+            return Err(FatError::InvalidCluster {
+                cluster: cluster as u32,
+                max,
+            });
+        }
+        let sector =
+            self.data_start_sector() as u64 + (cluster - 2) as u64 * self.br.cluster_size as u64;
+        Ok(sector * self.br.sector_size as u64)
     }
 
-    fn fat_lookup(&mut self, cluster: u16) -> io::Result<u16> {
-        let seek = self.fat_start_sector() * self.br.sector_size as u32 + ((cluster as u32) << 1);
-        println!(
-            "{:x} {:x} {:x}",
+    pub fn free_space(&mut self) -> Result<u64, FatError> {
+        // free clusters of the first FAT, in bytes
+        let mut fat = vec![0u8; self.br.fat_size as usize * self.br.sector_size as usize];
+        let seek = self.fat_start_sector() * self.br.sector_size as u32;
+        self.file.seek(io::SeekFrom::Start(seek as u64))?;
+        self.file.read_exact(&mut fat)?;
+
+        let mut free = 0;
+        for cluster in 2..self.cluster_count() as usize + 2 {
+            use byteorder::{ByteOrder, LittleEndian};
+            let offset = match self.fat_type {
+                FatType::Fat12 => cluster + cluster / 2,
+                FatType::Fat16 => cluster * 2,
+            };
+            // the FAT size comes from the boot record too
+            let raw = fat
+                .get(offset..offset + 2)
+                .ok_or(FatError::InvalidBootRecord(
+                    "FAT smaller than the data region",
+                ))?;
+            let packed = LittleEndian::read_u16(raw);
+            let value = match self.fat_type {
+                FatType::Fat12 if cluster & 1 == 0 => packed & 0x0fff,
+                FatType::Fat12 => packed >> 4,
+                FatType::Fat16 => packed,
+            };
+            if value == 0 {
+                free += 1;
+            }
+        }
+        let cluster_bytes = self.br.cluster_size as u64 * self.br.sector_size as u64;
+        Ok(free * cluster_bytes)
+    }
+
+    fn chain_end(&self) -> u16 {
+        // FAT entries from this value are reserved,
+        // bad cluster or end of chain markers
+        match self.fat_type {
+            FatType::Fat12 => 0xff0,
+            FatType::Fat16 => 0xfff0,
+        }
+    }
+
+    fn fat_lookup(&mut self, cluster: u16) -> Result<u16, FatError> {
+        let fat_start = self.fat_start_sector() as u64 * self.br.sector_size as u64;
+        let seek = match self.fat_type {
+            // 12 bits entries: 2 entries every 3 bytes
+            FatType::Fat12 => fat_start + cluster as u64 + (cluster as u64 >> 1),
+            FatType::Fat16 => fat_start + ((cluster as u64) << 1),
+        };
+        debug!(
+            "FAT entry of cluster {:x} at {:x}, FAT sector {:x}, sector size {:x}",
+            cluster,
             seek,
             self.fat_start_sector(),
             self.br.sector_size
         );
-        self.file.seek(io::SeekFrom::Start(seek as u64))?;
-        self.file.read_u16::<byteorder::LittleEndian>()
+        self.file.seek(io::SeekFrom::Start(seek))?;
+        let mut raw = [0u8; 2];
+        self.file.read_exact(&mut raw)?;
+        let value = byteorder::LittleEndian::read_u16(&raw);
+        Ok(match self.fat_type {
+            // even entries are in the low 12 bits,
+            // odd ones in the high 12 bits
+            FatType::Fat12 if cluster & 1 == 0 => value & 0x0fff,
+            FatType::Fat12 => value >> 4,
+            FatType::Fat16 => value,
+        })
     }
 
-    pub fn read_directory(&mut self, dir: Directory) -> io::Result<Vec<DirectoryEntry>> {
+    pub fn read_directory(&mut self, dir: Directory) -> Result<Vec<DirectoryEntry>, FatError> {
         let mut cluster = 0;
-        let (start_sector, entry_count, is_root) = match dir.inner {
-            DirType::Root(start, count) => (start as u32, count, true),
+        let (seek, entry_count, is_root) = match dir.inner {
+            DirType::Root(start, count) => (start as u64 * self.br.sector_size as u64, count, true),
             DirType::Regular(start) => {
                 cluster = start;
+                let seek = self.cluster_start(cluster)?;
                 let fat = self.fat_lookup(cluster)?;
-                println!("read regular dir {:x} {:x}", fat, cluster);
+                debug!("read regular dir {:x} {:x}", fat, cluster);
                 if fat < 2 {
                     return Ok(Vec::new());
                 }
                 (
-                    self.cluster_start(cluster),
-                    (self.br.cluster_size as u16 * self.br.sector_size as u16) >> 5,
+                    seek,
+                    // entries per cluster, the product doesn't fit on 16 bits
+                    ((self.br.cluster_size as u32 * self.br.sector_size as u32) >> 5) as u16,
                     false,
                 )
             }
         };
 
-        let seek = start_sector * self.br.sector_size as u32;
-        self.file.seek(io::SeekFrom::Start(seek as u64))?;
+        self.file.seek(io::SeekFrom::Start(seek))?;
         let mut entries = Vec::with_capacity(64);
         let mut count = 0;
+        // clusters already read, a corrupted chain could loop
+        let mut visited = BTreeSet::new();
+        visited.insert(cluster);
 
         loop {
-            use byteorder::LittleEndian;
+            use byteorder::{ByteOrder, LittleEndian};
 
             // end of current cluster?
             if count == entry_count {
                 if is_root {
+                    // fixed size root directory
+                    break;
+                }
+                // next cluster?
+                let next = self.fat_lookup(cluster)?;
+                if !(2..self.chain_end()).contains(&next) {
                     break;
-                } else {
-                    // next cluster?
-                    if cluster > 0xffef {
-                        break;
-                    }
-                    cluster = self.fat_lookup(cluster)?;
-                    if cluster < 2 {
-                        break;
-                    }
-                    // yes
-                    let start_sector = self.cluster_start(cluster);
-                    let seek = start_sector * self.br.sector_size as u32;
-                    self.file.seek(io::SeekFrom::Start(seek as u64))?;
-                    count = 0;
                 }
+                // yes, entry_count more entries
+                if !visited.insert(next) {
+                    return Err(FatError::CyclicFatChain(next as u32));
+                }
+                cluster = next;
+                let seek = self.cluster_start(cluster)?;
+                self.file.seek(io::SeekFrom::Start(seek))?;
+                count = 0;
             }
 
             let mut name = [0u8; 8];
@@ -200,11 +345,13 @@ impl FileSystem {
                 break;
             }
 
-            let flags = self.file.read_u8()?;
+            // the rest of the 32 bytes entry
+            let mut raw = [0u8; 21];
+            self.file.read_exact(&mut raw)?;
+            let flags = raw[0];
             // skip various fields
-            self.file.seek(io::SeekFrom::Current(14))?;
-            let first_cluster = self.file.read_u16::<LittleEndian>()?;
-            let size = self.file.read_u32::<LittleEndian>()?;
+            let first_cluster = LittleEndian::read_u16(&raw[15..]);
+            let size = LittleEndian::read_u32(&raw[17..]);
 
             if flags != 0xf {
                 entries.push(DirectoryEntry {
@@ -222,11 +369,13 @@ impl FileSystem {
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct File_ {
     first_cluster: u16,
     size: u32,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum DirType {
     // root dir: first sector, entry count
     Root(u32, u16),
@@ -234,10 +383,12 @@ pub enum DirType {
     Regular(u16),
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct Directory {
     inner: DirType,
 }
 
+#[derive(Clone, Debug)]
 pub struct DirectoryEntry {
     name: [u8; 8],
     ext: [u8; 3],
@@ -252,16 +403,29 @@ pub enum EntryType {
 }
 
 impl DirectoryEntry {
-    pub fn name(&self) -> &str {
-        str::from_utf8(&self.name).unwrap().trim_end()
+    pub fn name(&self) -> Cow<'_, str> {
+        decode_short_name(&self.name)
+    }
+
+    pub fn extension(&self) -> Cow<'_, str> {
+        decode_short_name(&self.ext)
+    }
+
+    pub fn full_name(&self) -> String {
+        // same rules as the FAT32 entries
+        short_full_name(&self.name, &self.ext)
     }
 
-    pub fn extension(&self) -> &str {
-        str::from_utf8(&self.ext).unwrap().trim_end()
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    pub fn is_directory(&self) -> bool {
+        self.flags & 0x10 != 0
     }
 
     pub fn entry_type(&self) -> EntryType {
-        if self.flags & 0x10 != 0 {
+        if self.is_directory() {
             EntryType::Dir(Directory {
                 inner: DirType::Regular(self.first_cluster),
             })
@@ -274,10 +438,159 @@ impl DirectoryEntry {
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "std"))]
 mod tests {
+    use super::*;
+    use byteorder::{ByteOrder, LittleEndian};
+
     #[test]
     fn it_works() {
         assert_eq!(2 + 2, 4);
     }
+
+    // FAT12 image: 512 bytes sectors, 1 sector per cluster,
+    // 1 reserved sector, 2 FATs of 1 sector, 16 root entries
+    fn fat12_image() -> Vec<u8> {
+        let mut data = vec![0u8; 100 * 512];
+        LittleEndian::write_u16(&mut data[11..], 512);
+        data[13] = 1;
+        LittleEndian::write_u16(&mut data[14..], 1);
+        data[16] = 2;
+        LittleEndian::write_u16(&mut data[17..], 16);
+        LittleEndian::write_u16(&mut data[19..], 100);
+        LittleEndian::write_u16(&mut data[22..], 1);
+        data[38] = 0x29;
+        data[43..54].copy_from_slice(b"FLOPPY     ");
+        data
+    }
+
+    fn fat12_entry(data: &mut [u8], cluster: usize, value: u16) {
+        // both FATs, 12 bits packed entries
+        for fat in [512, 1024] {
+            let offset = fat + cluster + cluster / 2;
+            let packed = LittleEndian::read_u16(&data[offset..]);
+            let packed = if cluster & 1 == 0 {
+                (packed & 0xf000) | value
+            } else {
+                (packed & 0x000f) | (value << 4)
+            };
+            LittleEndian::write_u16(&mut data[offset..], packed);
+        }
+    }
+
+    #[test]
+    fn full_names() {
+        let entry = |raw: &[u8; 11]| {
+            let mut name = [0u8; 8];
+            let mut ext = [0u8; 3];
+            name.copy_from_slice(&raw[..8]);
+            ext.copy_from_slice(&raw[8..]);
+            DirectoryEntry {
+                name,
+                ext,
+                flags: 0x20,
+                first_cluster: 0,
+                size: 0,
+            }
+        };
+        assert_eq!(entry(b"README  TXT").full_name(), "README.TXT");
+        assert_eq!(entry(b"MAKEFILE   ").full_name(), "MAKEFILE");
+        assert_eq!(entry(b"A B     C  ").full_name(), "A B.C");
+        // only spaces are padding
+        assert_eq!(entry(b"TAB\t    X\t ").full_name(), "TAB\t.X\t");
+        assert_eq!(entry(b"\xffBIN    DAT").full_name(), "\u{fffd}BIN.DAT");
+    }
+
+    #[test]
+    fn fat12_directory_chain() {
+        let mut data = fat12_image();
+        // root directory in sector 3, data from sector 4
+        data[3 * 512..][..11].copy_from_slice(b"DIR        ");
+        data[3 * 512 + 11] = 0x10;
+        LittleEndian::write_u16(&mut data[3 * 512 + 26..], 2);
+        // sub directory: clusters 2 -> 3, 17 entries
+        for i in 0..17 {
+            data[4 * 512 + i * 32..][..11].copy_from_slice(b"FILE    BIN");
+        }
+        fat12_entry(&mut data, 0, 0xff8);
+        fat12_entry(&mut data, 1, 0xfff);
+        fat12_entry(&mut data, 2, 3);
+        fat12_entry(&mut data, 3, 0xfff);
+
+        let mut fs = FileSystem::from_reader(io::Cursor::new(data)).unwrap();
+        assert_eq!(fs.fat_type(), FatType::Fat12);
+        assert_eq!(fs.cluster_count(), 96);
+        assert_eq!(fs.fat_lookup(2).unwrap(), 3);
+        assert_eq!(fs.fat_lookup(3).unwrap(), 0xfff);
+
+        let root = fs.root_directory();
+        let entries = fs.read_directory(root).unwrap();
+        assert_eq!(entries.len(), 1);
+        let dir = match entries[0].entry_type() {
+            EntryType::Dir(dir) => dir,
+            EntryType::File(_) => panic!("DIR is a directory"),
+        };
+        assert_eq!(fs.read_directory(dir).unwrap().len(), 17);
+    }
+
+    #[test]
+    fn malformed_boot_records() {
+        let open = |data: Vec<u8>| FileSystem::from_reader(io::Cursor::new(data));
+        let mut data = fat12_image();
+        data[38] = 0x12;
+        assert!(matches!(open(data), Err(FatError::InvalidBootRecord(_))));
+        let mut data = fat12_image();
+        data[13] = 0;
+        assert!(matches!(open(data), Err(FatError::InvalidBootRecord(_))));
+        let mut data = fat12_image();
+        data[43] = 0xff;
+        assert!(matches!(open(data), Err(FatError::Utf8Error(_))));
+        assert!(matches!(open(vec![0u8; 20]), Err(FatError::Io(_))));
+
+        // sub directory looping on itself, and one at cluster 0
+        let mut data = fat12_image();
+        data[3 * 512..][..11].copy_from_slice(b"LOOP       ");
+        data[3 * 512 + 11] = 0x10;
+        LittleEndian::write_u16(&mut data[3 * 512 + 26..], 2);
+        for i in 0..16 {
+            data[4 * 512 + i * 32..][..11].copy_from_slice(b"FILE    BIN");
+        }
+        fat12_entry(&mut data, 2, 2);
+        let mut fs = open(data).unwrap();
+        let loop_dir = Directory {
+            inner: DirType::Regular(2),
+        };
+        assert!(matches!(
+            fs.read_directory(loop_dir),
+            Err(FatError::CyclicFatChain(2))
+        ));
+        let null = Directory {
+            inner: DirType::Regular(0),
+        };
+        assert!(matches!(
+            fs.read_directory(null),
+            Err(FatError::InvalidCluster { cluster: 0, .. })
+        ));
+    }
+
+    #[test]
+    fn short_fat() {
+        // 100 clusters but a FAT of 1 sector for 2 bytes entries
+        let mut data = fat12_image();
+        LittleEndian::write_u16(&mut data[19..], 5000);
+        data.resize(5000 * 512, 0);
+        let mut fs = FileSystem::from_reader(io::Cursor::new(data)).unwrap();
+        assert_eq!(fs.fat_type(), FatType::Fat16);
+        assert!(matches!(
+            fs.free_space(),
+            Err(FatError::InvalidBootRecord(_))
+        ));
+
+        let mut data = fat12_image();
+        LittleEndian::write_u16(&mut data[22..], 0);
+        assert!(matches!(
+            FileSystem::from_reader(io::Cursor::new(data)),
+            Err(FatError::InvalidBootRecord(_))
+        ));
+    }
 }
@@ -1,63 +1,161 @@
-use byteorder::ReadBytesExt;
-use std::io::{Read, Seek};
-use std::{fs, io, path, str};
+use crate::attributes::FileAttributes;
+use crate::error::FatError;
+use crate::filesystem::{FatType, VolumeInfo};
+use crate::io::{self, Read, Seek, Write};
+use crate::timestamp::Timestamp;
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::{borrow, format, string::String, string::ToString, vec, vec::Vec};
+use core::{cmp, fmt, marker, str};
+#[cfg(feature = "std")]
+use std::{fs, path};
 
-// normally read from boot record,
-// here assumed to be 512 bytes
-const SECTOR_SIZE: u32 = 0x200;
+// FAT entries greater or equal to this value
+// mark the last cluster of a chain
+pub(crate) const END_OF_CHAIN: u32 = 0x0ffffff8;
 
-pub struct FAT32 {
-    // underlying file descriptor
-    file: fs::File,
+// FAT entry of a cluster that must not be used
+const BAD_CLUSTER: u32 = 0x0ffffff7;
+
+// number of FAT sectors kept in memory
+const DEFAULT_CACHE_SIZE: usize = 16;
+
+// FSInfo value of an unknown free cluster count
+const UNKNOWN_FREE_COUNT: u32 = 0xffffffff;
+
+// access modes of a FAT32 handle, only ReadWrite
+// handles can modify the image
+#[derive(Debug)]
+pub struct ReadOnly;
+#[derive(Debug)]
+pub struct ReadWrite;
+
+// flushes the modified sectors of a handle
+type WriteBack<B, M> = fn(&mut FAT32<B, M>) -> io::Result<()>;
+
+// image of the handles opened from a path
+#[cfg(feature = "std")]
+type DefaultBackend = fs::File;
+#[cfg(not(feature = "std"))]
+type DefaultBackend = io::Cursor<Vec<u8>>;
+
+#[derive(Debug)]
+pub struct FAT32<B = DefaultBackend, M = ReadOnly> {
+    // underlying image: file descriptor,
+    // or anything readable and seekable
+    file: B,
 
     // BIOS Parameter Block fields,
     // basic info about the volume:
-    reserved_sectors: u32, // number of reserved sectors (incl boot record)
-    sector_count: u32,     // total number of sectors on the FS
-    fat_size: u32,         // size of a FAT, in sectors (i.e. sectors/size)
-    root_dir: u32,         // first cluster of root directory
-    label: [u8; 11],       // file system name (aka label)
-}
+    sector_size: u32,         // size of a sector, in bytes
+    cluster_size: u32,        // size of a cluster, in sectors
+    reserved_sectors: u32,    // number of reserved sectors (incl boot record)
+    fat_count: u32,           // number of FATs, typically 2
+    sector_count: u32,        // total number of sectors on the FS
+    fat_size: u32,            // size of a FAT, in sectors (i.e. sectors/size)
+    ext_flags: ExtendedFlags, // FAT mirroring flags
+    root_dir: u32,            // first cluster of root directory
+    fs_info: u32,             // sector of the FSInfo structure
+
+    // every field of the boot record, as read when opening
+    boot: BootSector,
 
-impl FAT32 {
-    pub fn new(path: &path::Path) -> io::Result<FAT32> {
-        use byteorder::LittleEndian;
+    // recently read FAT sectors
+    cache: SectorCache,
 
+    // sectors modified but not written to the image yet
+    dirty: BTreeMap<u32, Vec<u8>>,
+    // writes the dirty sectors back, only set for ReadWrite
+    write_back: Option<WriteBack<B, M>>,
+
+    // access mode, ReadOnly or ReadWrite
+    mode: marker::PhantomData<M>,
+}
+
+#[cfg(feature = "std")]
+impl FAT32<fs::File> {
+    pub fn new(path: &path::Path) -> Result<FAT32, FatError> {
         // open the file descriptor and read the
         // information in the boot record sector
-        let mut file = fs::File::open(path)?;
-
-        // skip boot jump and OEM identifier
-        file.seek(io::SeekFrom::Start(11))?;
-        let sector_size = file.read_u16::<LittleEndian>()?;
-        assert!(sector_size as u32 == SECTOR_SIZE);
-        let cluster_size = file.read_u8()?;
-        assert!(cluster_size == 1);
-        let reserved_sectors = file.read_u16::<LittleEndian>()? as u32;
-        let fat_count = file.read_u8()?;
-        assert!(fat_count == 1);
-
-        // extended FAT32 boot record
-        file.seek(io::SeekFrom::Start(32))?;
-        let sector_count = file.read_u32::<LittleEndian>()?;
-        let fat_size = file.read_u32::<LittleEndian>()?;
-        // skip flags and version
-        let _flags = file.read_u16::<LittleEndian>()?;
-        let _version = file.read_u16::<LittleEndian>()?;
-        let root_dir = file.read_u32::<LittleEndian>()?;
-
-        // label: 11 ascii bytes padded with spaces
-        let mut label = [0u8; 11];
-        file.seek(io::SeekFrom::Start(71))?;
-        file.read_exact(&mut label)?;
+        FAT32::from_reader(fs::File::open(path)?)
+    }
+
+    pub fn with_cache_size(path: &path::Path, sectors: usize) -> Result<FAT32, FatError> {
+        // same as new, keeping up to `sectors` FAT
+        // sectors in memory (0 disables the cache)
+        let mut fs = FAT32::new(path)?;
+        fs.cache = SectorCache::new(sectors);
+        Ok(fs)
+    }
+}
+
+#[cfg(feature = "std")]
+impl FAT32<fs::File, ReadWrite> {
+    pub fn open_rw(path: &path::Path) -> Result<FAT32<fs::File, ReadWrite>, FatError> {
+        // same as new, but allows modifying the image
+        let file = fs::OpenOptions::new().read(true).write(true).open(path)?;
+        FAT32::from_reader_rw(file)
+    }
+}
+
+impl<'a> FAT32<io::Cursor<&'a [u8]>> {
+    pub fn from_bytes(data: &'a [u8]) -> Result<FAT32<io::Cursor<&'a [u8]>>, FatError> {
+        // read only image held in memory
+        FAT32::from_reader(io::Cursor::new(data))
+    }
+}
+
+#[cfg(feature = "std")]
+impl FAT32<io::Cursor<Vec<u8>>> {
+    pub fn from_stdin() -> Result<FAT32<io::Cursor<Vec<u8>>>, FatError> {
+        // stdin can't seek, e.g. with `dd if=/dev/sdb | ...`
+        FAT32::from_unseekable(std::io::stdin().lock())
+    }
+
+    fn from_unseekable<R: Read>(mut reader: R) -> Result<FAT32<io::Cursor<Vec<u8>>>, FatError> {
+        // the whole input is kept in memory
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        if data.len() > 256 << 20 {
+            warn!(
+                "buffered {} MB of input, is this a whole disk?",
+                data.len() >> 20
+            );
+        }
+        FAT32::from_reader(io::Cursor::new(data))
+    }
+}
+
+impl<B: Read + Seek> FAT32<B> {
+    pub fn from_reader(file: B) -> Result<FAT32<B>, FatError> {
+        // read only handle on any seekable reader
+        FAT32::from_backend(file)
+    }
+}
+
+impl<B: Read + Seek, M> FAT32<B, M> {
+    fn from_backend(mut file: B) -> Result<FAT32<B, M>, FatError> {
+        // the FAT32 BIOS Parameter Block ends at byte 90
+        let mut sector = [0u8; 90];
+        file.seek(io::SeekFrom::Start(0))?;
+        file.read_exact(&mut sector)?;
+        let boot = BootSector::parse(&sector)?;
 
         Ok(FAT32 {
             file,
-            reserved_sectors,
-            sector_count,
-            fat_size,
-            root_dir,
-            label,
+            sector_size: boot.bytes_per_sector as u32,
+            cluster_size: boot.sectors_per_cluster as u32,
+            reserved_sectors: boot.reserved_sectors as u32,
+            fat_count: boot.num_fats as u32,
+            sector_count: boot.total_sectors,
+            fat_size: boot.fat_size,
+            ext_flags: ExtendedFlags::from(boot.ext_flags),
+            root_dir: boot.root_cluster,
+            fs_info: boot.fs_info_sector as u32,
+            boot,
+            cache: SectorCache::new(DEFAULT_CACHE_SIZE),
+            dirty: BTreeMap::new(),
+            write_back: None,
+            mode: marker::PhantomData,
         })
     }
 
@@ -68,12 +166,36 @@ impl FAT32 {
 
     pub fn volume_size(&self) -> u32 {
         // full size of the volume, in bytes
-        self.sector_count() * SECTOR_SIZE
+        // saturated for volumes of 4 GiB or more
+        self.sector_count().saturating_mul(self.sector_size)
     }
 
     pub fn volume_name(&self) -> &str {
-        // remove padding spaces in volume name
-        str::from_utf8(&self.label).unwrap().trim_end()
+        // remove padding spaces (or zeros, when the
+        // label was removed) in volume name
+        str::from_utf8(&self.boot.volume_label)
+            .unwrap()
+            .trim_end_matches([' ', '\0'])
+    }
+
+    pub fn volume_serial_number(&self) -> u32 {
+        // set when formatting, and usually
+        // derived from the date and time
+        self.boot.volume_serial
+    }
+
+    pub fn oem_name(&self) -> &str {
+        self.boot.oem_name.as_str()
+    }
+
+    pub fn boot_sector(&self) -> &BootSector {
+        // raw values, the other getters interpret them
+        &self.boot
+    }
+
+    pub fn cluster_size_bytes(&self) -> u32 {
+        // size of a cluster, in bytes
+        self.cluster_size * self.sector_size
     }
 
     fn fat_start_sector(&self) -> u32 {
@@ -81,26 +203,354 @@ impl FAT32 {
         self.reserved_sectors
     }
 
+    fn fat_copy_start(&self, fat: u32) -> u32 {
+        // FATs are stored one after the other
+        self.fat_start_sector() + fat * self.fat_size
+    }
+
+    fn fat_copy_offset(&self, fat: u32) -> u64 {
+        // in bytes, which may not fit on 32 bits
+        self.fat_copy_start(fat) as u64 * self.sector_size as u64
+    }
+
+    fn active_fat(&self) -> u32 {
+        // FAT to read from: the one selected in the flags
+        // if mirroring is disabled, otherwise the first one
+        if self.ext_flags.mirroring_disabled {
+            self.ext_flags.active_fat as u32
+        } else {
+            0
+        }
+    }
+
+    pub fn extended_flags(&self) -> ExtendedFlags {
+        self.ext_flags
+    }
+
     fn data_start_sector(&self) -> u32 {
-        // data (i.e. clusters) start after FAT
-        self.fat_start_sector() + self.fat_size
+        // data (i.e. clusters) start after the FATs
+        self.fat_copy_start(self.fat_count)
+    }
+
+    pub fn total_cluster_count(&self) -> u32 {
+        // number of clusters in the data region, 0 if the
+        // FATs don't even fit in the volume (or for null clusters)
+        self.sector_count
+            .saturating_sub(self.data_start_sector())
+            .checked_div(self.cluster_size)
+            .unwrap_or(0)
     }
 
-    fn cluster_start(&self, cluster: u32) -> u32 {
+    fn cluster_start(&self, cluster: u32) -> Result<u32, FatError> {
         // clusters start at the first sector after
         // the reserved sectors and the FAT.
         // clusters 0 and 1 have entries in the FAT
         // but do not actually exist on disk (hence -2)
-        assert!(cluster >= 2);
-        self.data_start_sector() + (cluster - 2)
+        self.check_cluster(cluster)?;
+        Ok(self.data_start_sector() + (cluster - 2) * self.cluster_size)
+    }
+
+    fn cluster_offset(&self, cluster: u32) -> Result<u64, FatError> {
+        // position of the cluster in the image, in bytes
+        Ok(self.cluster_start(cluster)? as u64 * self.sector_size as u64)
+    }
+
+    fn fat_lookup(&mut self, cluster: u32) -> Result<u32, FatError> {
+        use byteorder::{ByteOrder, LittleEndian};
+
+        // read the FAT entry describing a given cluster,
+        // 4 bytes / entry, from the cached FAT sector
+        let offset = cluster << 2;
+        let sector = self.fat_copy_start(self.active_fat()) + offset / self.sector_size;
+        let offset = (offset % self.sector_size) as usize;
+        if let Some(data) = self.cache.get(sector) {
+            return Ok(LittleEndian::read_u32(&data[offset..]) & 0x0fffffff);
+        }
+
+        let mut data = vec![0u8; self.sector_size as usize];
+        self.read_at(sector as u64 * self.sector_size as u64, &mut data)?;
+        // the 4 high bits of a FAT32 entry are reserved
+        let value = LittleEndian::read_u32(&data[offset..]) & 0x0fffffff;
+        self.cache.insert(sector, data);
+        Ok(value)
+    }
+
+    pub fn cache_stats(&self) -> (u64, u64) {
+        // hits and misses of the FAT sectors cache
+        (self.cache.hits, self.cache.misses)
+    }
+
+    fn fsinfo_start(&self) -> Result<u64, FatError> {
+        // 0 or 0xffff: there is no FSInfo sector
+        if self.fs_info == 0 || self.fs_info == 0xffff {
+            return Err(FatError::InvalidBootRecord("no FSInfo sector"));
+        }
+        Ok(self.fs_info as u64 * self.sector_size as u64)
+    }
+
+    pub fn read_fsinfo(&mut self) -> Result<FsInfo, FatError> {
+        use byteorder::{ByteOrder, LittleEndian};
+
+        // the structure is delimited by 2 signatures
+        let start = self.fsinfo_start()?;
+        let mut raw = [0u8; 496];
+        self.read_at(start, &mut raw)?;
+        let lead = LittleEndian::read_u32(&raw[0..]);
+        let signature = LittleEndian::read_u32(&raw[484..]);
+        if lead != 0x41615252 || signature != 0x61417272 {
+            return Err(FatError::InvalidBootRecord("invalid FSInfo signature"));
+        }
+
+        Ok(FsInfo {
+            free_clusters: LittleEndian::read_u32(&raw[488..]),
+            next_free: LittleEndian::read_u32(&raw[492..]),
+        })
+    }
+
+    pub fn validate_boot_sector(&mut self) -> Result<(), FatError> {
+        use byteorder::{ByteOrder, LittleEndian};
+
+        // stricter than opening the volume: every
+        // field FAT32 requires a given value for
+        let mut boot = [0u8; 512];
+        self.read_at(0, &mut boot)?;
+        if boot[510..] != [0x55, 0xaa] {
+            return Err(FatError::InvalidBootRecord("missing 0x55aa boot signature"));
+        }
+        if boot[66] != 0x28 && boot[66] != 0x29 {
+            return Err(FatError::InvalidBootRecord(
+                "invalid extended boot signature",
+            ));
+        }
+        let sector_size = LittleEndian::read_u16(&boot[11..]);
+        if ![512, 1024, 2048, 4096].contains(&sector_size) {
+            return Err(FatError::InvalidBootRecord("unsupported sector size"));
+        }
+        if !boot[13].is_power_of_two() || boot[13] > 128 {
+            return Err(FatError::InvalidBootRecord("invalid sectors per cluster"));
+        }
+        if boot[16] != 1 && boot[16] != 2 {
+            return Err(FatError::InvalidBootRecord("invalid number of FATs"));
+        }
+        // fields only used by FAT12 and FAT16
+        if LittleEndian::read_u16(&boot[17..]) != 0 {
+            return Err(FatError::InvalidBootRecord("root entry count must be 0"));
+        }
+        if LittleEndian::read_u16(&boot[19..]) != 0 {
+            return Err(FatError::InvalidBootRecord(
+                "16 bits sector count must be 0",
+            ));
+        }
+        if LittleEndian::read_u16(&boot[22..]) != 0 {
+            return Err(FatError::InvalidBootRecord("16 bits FAT size must be 0"));
+        }
+        Ok(())
+    }
+
+    pub fn backup_boot_sector(&mut self) -> Result<BootSector, FatError> {
+        // the copy of the boot record written when formatting,
+        // usually in sector 6
+        let mut sector = [0u8; 90];
+        self.read_at(self.backup_boot_offset()?, &mut sector)?;
+        BootSector::parse(&sector)
+    }
+
+    fn backup_boot_offset(&self) -> Result<u64, FatError> {
+        // the copy is in the reserved sectors, 0 means there is none
+        let sector = self.boot.backup_boot_sector as u32;
+        if sector == 0 || sector >= self.reserved_sectors {
+            return Err(FatError::InvalidBootRecord("no backup boot sector"));
+        }
+        Ok(sector as u64 * self.sector_size as u64)
+    }
+
+    pub fn scan_free_clusters(&mut self) -> Result<u32, FatError> {
+        // count the free entries of the active FAT
+        Ok(self.free_space_map()?.free_count())
+    }
+
+    pub fn free_space_map(&mut self) -> Result<FreeSpaceMap, FatError> {
+        // free entries of the active FAT, skipping
+        // the 2 reserved ones
+        let mut map = FreeSpaceMap::new(self.total_cluster_count());
+        self.scan_fat(|cluster, value| {
+            if cluster >= 2 && value == 0 {
+                map.set_free(cluster);
+            }
+        })?;
+        Ok(map)
+    }
+
+    pub fn cluster_statistics(&mut self) -> Result<ClusterStats, FatError> {
+        // every entry of the active FAT, in a single pass
+        let mut stats = ClusterStats {
+            total: self.total_cluster_count() + 2,
+            free: 0,
+            bad: 0,
+            reserved: 0,
+            used: 0,
+        };
+        self.scan_fat(|cluster, value| match value {
+            _ if cluster < 2 => stats.reserved += 1,
+            0 => stats.free += 1,
+            BAD_CLUSTER => stats.bad += 1,
+            _ => stats.used += 1,
+        })?;
+        Ok(stats)
+    }
+
+    fn scan_fat(&mut self, mut visit: impl FnMut(u32, u32)) -> Result<(), FatError> {
+        use byteorder::{ByteOrder, LittleEndian};
+
+        // calls `visit` with each cluster number and its entry
+        // in the active FAT, reading one sector at a time
+        let max = self.total_cluster_count() + 2;
+        let per_sector = self.sector_size >> 2;
+        let mut sector = vec![0u8; self.sector_size as usize];
+
+        let start = self.fat_copy_offset(self.active_fat());
+        for cluster in 0..max {
+            if cluster % per_sector == 0 {
+                let seek = start + (cluster / per_sector) as u64 * self.sector_size as u64;
+                self.read_at(seek, &mut sector)?;
+            }
+            let offset = ((cluster % per_sector) << 2) as usize;
+            visit(
+                cluster,
+                LittleEndian::read_u32(&sector[offset..]) & 0x0fffffff,
+            );
+        }
+        Ok(())
+    }
+
+    pub fn free_cluster_count(&mut self) -> Result<u32, FatError> {
+        // from FSInfo when the count is known,
+        // scanning the FAT otherwise
+        match self.read_fsinfo() {
+            Ok(info) if info.free_clusters != UNKNOWN_FREE_COUNT => Ok(info.free_clusters),
+            Ok(_) | Err(FatError::InvalidBootRecord(_)) => self.scan_free_clusters(),
+            Err(err) => Err(err),
+        }
+    }
+
+    pub fn free_space(&mut self) -> Result<u64, FatError> {
+        // free space, in bytes
+        Ok(self.free_cluster_count()? as u64 * self.cluster_size_bytes() as u64)
+    }
+
+    pub fn volume_info(&mut self) -> Result<VolumeInfo, FatError> {
+        // everything comes from the boot record read when opening,
+        // but the free clusters (FSInfo, or a scan of the FAT)
+        let free_clusters = self.free_cluster_count()?;
+        Ok(VolumeInfo {
+            label: self.volume_name().to_string(),
+            serial: self.boot.volume_serial,
+            fat_type: FatType::Fat32,
+            oem_name: self.boot.oem_name.clone(),
+            sector_size: self.sector_size,
+            cluster_size_sectors: self.cluster_size,
+            total_sectors: self.sector_count,
+            total_clusters: self.total_cluster_count(),
+            free_clusters,
+            total_bytes: self.sector_count as u64 * self.sector_size as u64,
+            free_bytes: free_clusters as u64 * self.cluster_size_bytes() as u64,
+        })
+    }
+
+    pub fn read_sector(&mut self, sector: u32) -> Result<Vec<u8>, FatError> {
+        // raw content of any sector of the volume
+        self.check_sector(sector)?;
+        let mut data = vec![0u8; self.sector_size as usize];
+        self.read_at(sector as u64 * self.sector_size as u64, &mut data)?;
+        Ok(data)
+    }
+
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<(), FatError> {
+        // read from the image, then apply the
+        // sectors waiting to be written back
+        self.file.seek(io::SeekFrom::Start(offset))?;
+        self.file.read_exact(buf)?;
+        if self.dirty.is_empty() {
+            return Ok(());
+        }
+
+        let sector_size = self.sector_size as u64;
+        let end = offset + buf.len() as u64;
+        let mut sector = offset / sector_size;
+        while sector * sector_size < end {
+            if let Some(data) = self.dirty.get(&(sector as u32)) {
+                let base = sector * sector_size;
+                let start = cmp::max(offset, base);
+                let stop = cmp::min(end, base + sector_size);
+                buf[(start - offset) as usize..(stop - offset) as usize]
+                    .copy_from_slice(&data[(start - base) as usize..(stop - base) as usize]);
+            }
+            sector += 1;
+        }
+        Ok(())
+    }
+
+    fn check_sector(&self, sector: u32) -> Result<(), FatError> {
+        if sector >= self.sector_count {
+            return Err(FatError::InvalidSector {
+                sector,
+                max: self.sector_count.saturating_sub(1),
+            });
+        }
+        Ok(())
+    }
+
+    pub fn read_cluster(&mut self, cluster: u32) -> Result<Vec<u8>, FatError> {
+        // raw content of a data cluster
+        let mut data = vec![0u8; self.cluster_size_bytes() as usize];
+        let seek = self.cluster_offset(cluster)?;
+        self.read_at(seek, &mut data)?;
+        Ok(data)
+    }
+
+    fn check_cluster(&self, cluster: u32) -> Result<(), FatError> {
+        // data clusters are numbered from 2
+        let max = self.total_cluster_count() + 1;
+        if !(2..=max).contains(&cluster) {
+            return Err(FatError::InvalidCluster { cluster, max });
+        }
+        Ok(())
+    }
+
+    pub fn chain(&mut self, start: u32) -> FatChain<'_, B, M> {
+        // clusters of the chain starting at `start`
+        FatChain::new(self, start)
+    }
+
+    pub fn chain_to_vec(&mut self, start: u32) -> Result<Vec<u32>, FatError> {
+        // every cluster of the chain, in order
+        self.chain(start).collect()
+    }
+
+    pub fn cluster_chain_length(&mut self, start: u32) -> Result<u32, FatError> {
+        // number of clusters of the chain, without keeping them
+        let mut count = 0;
+        for cluster in self.chain(start) {
+            cluster?;
+            count += 1;
+        }
+        Ok(count)
     }
 
-    fn fat_lookup(&mut self, cluster: u32) -> io::Result<u32> {
-        // read the FAT entry describing a given cluster
-        // seek offset: beginning of FAT (in bytes) + 4 bytes / entry
-        let seek = self.fat_start_sector() * SECTOR_SIZE + (cluster << 2);
-        self.file.seek(io::SeekFrom::Start(seek as u64))?;
-        self.file.read_u32::<byteorder::LittleEndian>()
+    pub fn fragmentation_score(&mut self, file: File) -> Result<f32, FatError> {
+        // share of the links of the chain going elsewhere
+        // than the next cluster: 0 when contiguous,
+        // 1 when no two clusters are adjacent
+        if file.size == 0 {
+            return Ok(0.0);
+        }
+        let chain = self.chain_to_vec(file.cluster)?;
+        let links = chain.len().saturating_sub(1);
+        let contiguous = chain
+            .windows(2)
+            .filter(|pair| pair[1] == pair[0] + 1)
+            .count();
+        Ok((links - contiguous) as f32 / cmp::max(links, 1) as f32)
     }
 
     pub fn root_directory(&self) -> Directory {
@@ -111,119 +561,5499 @@ impl FAT32 {
         }
     }
 
-    pub fn read_directory(&mut self, dir: Directory) -> io::Result<Vec<DirectoryEntry>> {
-        let mut cluster = dir.cluster;
-        // entries per cluster: sector size / 32
-        let count = SECTOR_SIZE >> 5;
-        // vector initial capacity: 1 sector
-        // (will automatically grow if overflow)
-        let mut entries = Vec::with_capacity(count as usize);
+    pub fn read_directory(&mut self, dir: Directory) -> Result<Vec<DirectoryEntry>, FatError> {
+        self.iter_directory(dir).collect()
+    }
 
-        'outer: while cluster < 0xfffff0 {
-            use byteorder::LittleEndian;
+    pub fn count_entries(&mut self, dir: Directory) -> Result<(usize, usize), FatError> {
+        // (files, directories) counted while iterating,
+        // `.` and `..` excluded
+        let (mut files, mut dirs) = (0, 0);
+        for entry in self.iter_directory(dir) {
+            let entry = entry?;
+            if entry.is_dot_entry() {
+                continue;
+            }
+            if entry.is_directory() {
+                dirs += 1;
+            } else {
+                files += 1;
+            }
+        }
+        Ok((files, dirs))
+    }
 
-            // seek to beginning of cluster
-            let seek = self.cluster_start(cluster) * SECTOR_SIZE;
-            self.file.seek(io::SeekFrom::Start(seek as u64))?;
+    pub fn read_directory_clean(
+        &mut self,
+        dir: Directory,
+    ) -> Result<Vec<DirectoryEntry>, FatError> {
+        // without the `.` and `..` entries (labels are
+        // never part of a listing)
+        let mut entries = self.read_directory(dir)?;
+        entries.retain(|entry| !entry.is_dot_entry());
+        Ok(entries)
+    }
 
-            // read directory entries until we reach maximum number
-            // of entries/sector OR reach a termination marker
-            for _ in 0..count {
-                let mut name = [0u8; 11];
-                self.file.read_exact(&mut name)?;
+    pub fn read_directory_sorted(
+        &mut self,
+        dir: Directory,
+        key: SortKey,
+    ) -> Result<Vec<DirectoryEntry>, FatError> {
+        // `.` and `..` stay first, entries equal for `key`
+        // are ordered by name
+        let mut entries = self.read_directory(dir)?;
+        let name = |entry: &DirectoryEntry| {
+            entry
+                .long_name()
+                .map_or_else(|| entry.full_name(), String::from)
+                .to_ascii_uppercase()
+        };
+        entries.sort_by(|a, b| {
+            let by_key = match key {
+                SortKey::Name => cmp::Ordering::Equal,
+                SortKey::Size => a.size.cmp(&b.size),
+                SortKey::SizeDesc => b.size.cmp(&a.size),
+                SortKey::Modified => a.modified().cmp(&b.modified()),
+                SortKey::Created => a.created().cmp(&b.created()),
+            };
+            b.is_dot_entry()
+                .cmp(&a.is_dot_entry())
+                .then(by_key)
+                .then_with(|| name(a).cmp(&name(b)))
+        });
+        Ok(entries)
+    }
 
-                if name[0] == 0 {
-                    // end marker
-                    break 'outer;
-                }
+    pub fn read_directory_with_deleted(
+        &mut self,
+        dir: Directory,
+    ) -> Result<Vec<(bool, DirectoryEntry)>, FatError> {
+        // deleted entries too, flagged with true and with a `?` as
+        // first character, their clusters may have been reused
+        let mut entries = self.iter_directory(dir);
+        entries.with_deleted = true;
+        entries
+            .map(|entry| entry.map(|entry| (entry.deleted, entry)))
+            .collect()
+    }
 
-                let flags = self.file.read_u8()?;
-                // skip various fields
-                // TODO: hi bytes of cluster num
-                self.file.seek(io::SeekFrom::Current(14))?;
-                let cluster = self.file.read_u16::<LittleEndian>()? as u32;
-                let size = self.file.read_u32::<LittleEndian>()?;
+    pub fn read_directory_filtered(
+        &mut self,
+        dir: Directory,
+        pred: impl Fn(&DirectoryEntry) -> bool,
+    ) -> Result<Vec<DirectoryEntry>, FatError> {
+        // only the entries accepted by `pred` are kept
+        self.iter_directory(dir)
+            .filter(|entry| entry.as_ref().map_or(true, &pred))
+            .collect()
+    }
 
-                // flag 0xf = special entry for long filenames
-                // not supported atm.
-                if flags != 0xf {
-                    entries.push(DirectoryEntry {
-                        name,
-                        flags,
-                        cluster,
-                        size,
-                    });
-                }
+    pub fn glob(&mut self, dir: Directory, pattern: &str) -> Result<Vec<DirectoryEntry>, FatError> {
+        // entries whose short or long name match `pattern`,
+        // `*` for any characters and `?` for exactly one
+        let pattern: Vec<char> = pattern.chars().collect();
+        self.read_directory_filtered(dir, |entry| {
+            glob_match(&pattern, &entry.full_name())
+                || entry
+                    .long_name()
+                    .is_some_and(|name| glob_match(&pattern, name))
+        })
+    }
+
+    pub fn iter_directory(&mut self, dir: Directory) -> DirectoryIterator<'_, B, M> {
+        // lazy version of read_directory
+        let count = self.cluster_size_bytes() >> 5;
+        DirectoryIterator {
+            chain: self.chain(dir.cluster),
+            cluster: 0,
+            index: count,
+            count,
+            long_name: None,
+            done: false,
+            with_deleted: false,
+            with_labels: false,
+        }
+    }
+
+    fn volume_label_entry(&mut self) -> Result<Option<DirectoryEntry>, FatError> {
+        // at most one label, in the root directory
+        let root = self.root_directory();
+        let mut entries = self.iter_directory(root);
+        entries.with_labels = true;
+        entries
+            .find(|entry| entry.as_ref().map_or(true, |entry| entry.is_volume_label()))
+            .transpose()
+    }
+
+    pub fn volume_label_from_root_dir(&mut self) -> Result<Option<String>, FatError> {
+        // label as stored in the root directory, which may
+        // differ from the boot record one (volume_name)
+        let entry = self.volume_label_entry()?;
+        Ok(entry.map(|entry| decode_short_name(&entry.name).into_owned()))
+    }
+
+    pub fn read_file(&mut self, file: File) -> Result<Vec<u8>, FatError> {
+        let size = file.size as usize;
+        // grown as clusters are read, the entry size
+        // of a corrupted image can be anything
+        let mut data = Vec::new();
+        let mut buf = vec![0u8; self.cluster_size_bytes() as usize];
+
+        // read whole clusters following the chain until
+        // we have at least `size` bytes
+        let mut chain = FatChain::new(self, file.cluster);
+        while data.len() < size {
+            let cluster = match chain.next() {
+                Some(cluster) => cluster?,
+                // chain is shorter than the file size
+                None => return Err(FatError::UnexpectedEof),
+            };
+
+            let fs = &mut *chain.fs;
+            let seek = fs.cluster_offset(cluster)?;
+            fs.read_at(seek, &mut buf)?;
+            data.extend_from_slice(&buf);
+        }
+
+        // drop the padding at the end of the last cluster
+        data.truncate(size);
+        Ok(data)
+    }
+
+    pub fn read_file_at(
+        &mut self,
+        file: File,
+        offset: u64,
+        buf: &mut [u8],
+    ) -> Result<usize, FatError> {
+        // up to buf.len() bytes from `offset`, fewer
+        // near the end of the file, 0 past it
+        if offset >= file.size as u64 {
+            return Ok(0);
+        }
+        let cluster_size = self.cluster_size_bytes() as u64;
+        let len = cmp::min(buf.len() as u64, file.size as u64 - offset) as usize;
+
+        // the clusters before `offset` are only followed
+        let mut chain = FatChain::new(self, file.cluster);
+        let mut cluster_offset = offset % cluster_size;
+        for _ in 0..offset / cluster_size {
+            chain.next().ok_or(FatError::UnexpectedEof)??;
+        }
+
+        let mut read = 0;
+        while read < len {
+            let cluster = match chain.next() {
+                Some(cluster) => cluster?,
+                None => return Err(FatError::UnexpectedEof),
+            };
+            let count = cmp::min((cluster_size - cluster_offset) as usize, len - read);
+            let fs = &mut *chain.fs;
+            let seek = fs.cluster_offset(cluster)? + cluster_offset;
+            fs.read_at(seek, &mut buf[read..read + count])?;
+            read += count;
+            cluster_offset = 0;
+        }
+        Ok(read)
+    }
+
+    pub fn read_slack_space(&mut self, file: File) -> Result<Vec<u8>, FatError> {
+        // bytes of the last cluster after the end of the file,
+        // they may be left from a deleted file; nothing when
+        // the file fills its last cluster (or has none)
+        let cluster_size = self.cluster_size_bytes();
+        let slack_start = (file.size % cluster_size) as usize;
+        if slack_start == 0 {
+            return Ok(Vec::new());
+        }
+        let index = (file.size / cluster_size) as usize;
+        let last = self
+            .chain(file.cluster)
+            .nth(index)
+            .ok_or(FatError::UnexpectedEof)??;
+        let mut data = self.read_cluster(last)?;
+        Ok(data.split_off(slack_start))
+    }
+
+    pub fn find_entry(
+        &mut self,
+        dir: Directory,
+        name: &str,
+    ) -> Result<Option<DirectoryEntry>, FatError> {
+        // entry of `dir` called `name`, ignoring case,
+        // the scan stops at the first match
+        for entry in self.iter_directory(dir) {
+            let entry = entry?;
+            if entry.has_name(name) {
+                return Ok(Some(entry));
             }
+        }
+        Ok(None)
+    }
+
+    pub fn directory_entry_offset(&mut self, dir: Directory, name: &str) -> Result<u64, FatError> {
+        // byte position in the image of the 32 bytes short
+        // entry, where write-backs of its fields go
+        Ok(self.find_by_name(dir, name)?.offset)
+    }
 
-            // end of sector, read next cluster
-            cluster = self.fat_lookup(cluster)?;
+    pub fn open_path(&mut self, path: &str) -> Result<EntryType, FatError> {
+        match self.path_entry(path)? {
+            Some(entry) => Ok(entry.entry_type()),
+            None => Ok(EntryType::Dir(self.root_directory())),
         }
+    }
 
-        Ok(entries)
+    pub fn path_exists(&mut self, path: &str) -> Result<bool, FatError> {
+        // only a missing entry gives false, other errors
+        // (e.g. a damaged directory) are returned
+        match self.open_path(path) {
+            Ok(_) => Ok(true),
+            Err(FatError::NotFound) => Ok(false),
+            Err(err) => Err(err),
+        }
     }
-}
 
-// describes one entry in
-// a directory listing
-pub struct DirectoryEntry {
-    name: [u8; 11],
-    flags: u8,
-    cluster: u32,
-    size: u32,
-}
+    pub fn is_dir(&mut self, path: &str) -> Result<bool, FatError> {
+        match self.open_path(path) {
+            Ok(entry) => Ok(matches!(entry, EntryType::Dir(_))),
+            Err(FatError::NotFound) => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
 
-pub enum EntryType {
-    File(File),
-    Dir(Directory),
-}
+    pub fn is_file(&mut self, path: &str) -> Result<bool, FatError> {
+        match self.open_path(path) {
+            Ok(entry) => Ok(matches!(entry, EntryType::File(_))),
+            Err(FatError::NotFound) => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
 
-#[allow(dead_code)]
-pub struct File {
-    cluster: u32,
-    size: u32,
-}
+    pub fn stat(&mut self, path: &str) -> Result<FileStat, FatError> {
+        // metadata of the file at `path`
+        match self.path_entry(path)? {
+            Some(entry) if !entry.is_directory() => Ok(FileStat::from(&entry)),
+            _ => Err(FatError::IsDirectory),
+        }
+    }
 
-pub struct Directory {
-    cluster: u32,
-}
+    pub(crate) fn parent_directory<'p>(
+        &mut self,
+        path: &'p str,
+    ) -> Result<(Directory, &'p str), FatError> {
+        // directory holding the last component of `path`, and its name
+        let path = path.trim_end_matches('/');
+        let (parent, name) = path.rsplit_once('/').unwrap_or(("", path));
+        if name.is_empty() {
+            return Err(FatError::InvalidName);
+        }
+        match self.open_path(parent)? {
+            EntryType::Dir(dir) => Ok((dir, name)),
+            EntryType::File(_) => Err(FatError::NotADirectory),
+        }
+    }
 
-impl DirectoryEntry {
-    pub fn name(&self) -> &str {
-        // removes the padding spaces around the name
-        str::from_utf8(&self.name[..8]).unwrap().trim_end()
+    fn path_entry(&mut self, path: &str) -> Result<Option<DirectoryEntry>, FatError> {
+        // entry at the end of a `/` separated path from the root
+        // directory, None for the root itself (which has no entry),
+        // empty components are ignored
+        let mut entry: Option<DirectoryEntry> = None;
+        for name in path.split('/').filter(|name| !name.is_empty()) {
+            let dir = match entry.as_ref().map(DirectoryEntry::entry_type) {
+                None => self.root_directory(),
+                Some(EntryType::Dir(dir)) => dir,
+                Some(EntryType::File(_)) => return Err(FatError::NotADirectory),
+            };
+            entry = match self.find_entry(dir, name)?.ok_or(FatError::NotFound)? {
+                // ".." entries of the root subdirectories hold cluster 0
+                entry if entry.is_directory() && entry.cluster == 0 => None,
+                entry => Some(entry),
+            };
+        }
+        Ok(entry)
     }
 
-    pub fn extension(&self) -> &str {
-        // removes the padding spaces around the extension
-        str::from_utf8(&self.name[8..]).unwrap().trim_end()
+    pub fn walk(&mut self, root: Directory) -> WalkIterator<'_, B, M> {
+        // depth first traversal of the tree under `root`
+        WalkIterator::new(self, root, String::new())
     }
 
-    pub fn full_name(&self) -> String {
-        // returns the full name of the file : NAME.EXT
-        // uses a buffered String to concatenate name and ext
-        let mut name = String::with_capacity(12);
-        name.push_str(self.name());
-        let ext = self.extension();
-        if !ext.is_empty() {
-            name.push('.');
-            name.push_str(ext);
+    #[cfg(all(feature = "serde", feature = "std"))]
+    pub fn export_directory_tree(&mut self) -> Result<serde_json::Value, FatError> {
+        use serde_json::{Map, Value};
+
+        // one object per directory, mapping names to
+        // FileStat objects or to nested directories
+        let mut tree = Map::new();
+        let root = self.root_directory();
+        for item in self.walk(root) {
+            let item = item?;
+            let mut parts: Vec<&str> = item.path.split('/').skip(1).collect();
+            let name = parts.pop().unwrap_or_default();
+            // parents come first in the walk
+            let mut dir = &mut tree;
+            for part in parts {
+                dir = match dir.entry(part).or_insert_with(|| Value::Object(Map::new())) {
+                    Value::Object(map) => map,
+                    // file with the name of a directory
+                    _ => return Err(FatError::InvalidOperation),
+                };
+            }
+            let value = if item.entry.is_directory() {
+                Value::Object(Map::new())
+            } else {
+                serde_json::to_value(FileStat::from(&item.entry)).map_err(io::Error::from)?
+            };
+            dir.entry(name).or_insert(value);
         }
-        name
+        Ok(Value::Object(tree))
     }
 
-    pub fn entry_type(&self) -> EntryType {
-        // 0x10 = 00010000
-        // 5th bit of flags = directory or file
-        if self.flags & 0x10 != 0 {
-            EntryType::Dir(Directory {
-                cluster: self.cluster,
-            })
-        } else {
-            EntryType::File(File {
-                cluster: self.cluster,
-                size: self.size,
-            })
+    pub fn diff<C: Read + Seek, N>(
+        &mut self,
+        other: &mut FAT32<C, N>,
+        options: DiffOptions,
+    ) -> Result<Vec<DiffEntry>, FatError> {
+        // changes from this volume to `other`, by path; names
+        // are compared as FAT does, without the case
+        let old = self.path_map()?;
+        let mut new = other.path_map()?;
+        let mut changes = Vec::new();
+        for (key, old) in old {
+            let new = match new.remove(&key) {
+                Some(new) => new,
+                None => {
+                    changes.push(DiffEntry::Removed(old));
+                    continue;
+                }
+            };
+            match (old.entry.entry_type(), new.entry.entry_type()) {
+                (EntryType::Dir(_), EntryType::Dir(_)) => {}
+                (EntryType::File(a), EntryType::File(b)) => {
+                    let content_changed = a.size != b.size
+                        || (options.compare_content && self.read_file(a)? != other.read_file(b)?);
+                    if content_changed {
+                        changes.push(DiffEntry::Modified {
+                            path: new.path,
+                            old_size: a.size,
+                            new_size: b.size,
+                            content_changed,
+                        });
+                    }
+                }
+                // a file replaced by a directory, or the reverse
+                _ => {
+                    changes.push(DiffEntry::Removed(old));
+                    changes.push(DiffEntry::Added(new));
+                }
+            }
+        }
+        changes.extend(new.into_values().map(DiffEntry::Added));
+        Ok(changes)
+    }
+
+    fn path_map(&mut self) -> Result<BTreeMap<String, PathEntry>, FatError> {
+        // every entry of the volume, by uppercase path
+        let root = self.root_directory();
+        self.walk(root)
+            .map(|item| item.map(|item| (item.path.to_ascii_uppercase(), item)))
+            .collect()
+    }
+
+    #[cfg(feature = "std")]
+    pub fn extract_to_directory(
+        &mut self,
+        dest: &path::Path,
+        options: ExtractOptions,
+    ) -> Result<(), FatError> {
+        // copy the whole tree under `dest`, directories are
+        // created before the files they hold
+        let root = self.root_directory();
+        let items = self
+            .walk(root)
+            .collect::<Result<Vec<PathEntry>, FatError>>()?;
+        fs::create_dir_all(dest)?;
+        for item in items {
+            // a corrupted long name must not lead out of `dest`
+            let relative = path::Path::new(item.path.trim_start_matches('/'));
+            if !relative
+                .components()
+                .all(|part| matches!(part, path::Component::Normal(_)))
+            {
+                return Err(FatError::InvalidOperation);
+            }
+            let target = dest.join(relative);
+            let file = match item.entry.entry_type() {
+                EntryType::Dir(_) => {
+                    fs::create_dir_all(&target)?;
+                    continue;
+                }
+                EntryType::File(file) => file,
+            };
+            if !options.overwrite && target.exists() {
+                return Err(FatError::AlreadyExists);
+            }
+            fs::write(&target, self.read_file(file)?)?;
+
+            #[cfg(feature = "filetime")]
+            filetime::set_file_times(
+                &target,
+                filetime::FileTime::from_system_time(item.entry.accessed().into()),
+                filetime::FileTime::from_system_time(item.entry.modified().into()),
+            )?;
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "hashes")]
+    pub fn hash_file(&mut self, file: File) -> Result<[u8; 32], FatError> {
+        use sha2::{Digest, Sha256};
+
+        // SHA-256 of the contents, read through a FileReader
+        // rather than loading the whole file
+        let mut hasher = Sha256::new();
+        std::io::copy(&mut self.open_file(file), &mut hasher)?;
+        Ok(hasher.finalize().into())
+    }
+
+    #[cfg(feature = "hashes")]
+    pub fn hash_directory_tree(
+        &mut self,
+        dir: Directory,
+    ) -> Result<BTreeMap<String, [u8; 32]>, FatError> {
+        // digest of every file under `dir`, by path as given by walk
+        let items = self
+            .walk(dir)
+            .collect::<Result<Vec<PathEntry>, FatError>>()?;
+        let mut hashes = BTreeMap::new();
+        for item in items {
+            if let EntryType::File(file) = item.entry.entry_type() {
+                hashes.insert(item.path, self.hash_file(file)?);
+            }
+        }
+        Ok(hashes)
+    }
+
+    pub fn disk_usage(&mut self, dir: Directory) -> Result<u64, FatError> {
+        // sum of the file sizes under `dir`
+        Ok(self
+            .tree_files(dir)?
+            .iter()
+            .map(|entry| entry.size as u64)
+            .sum())
+    }
+
+    pub fn allocated_space(&mut self, dir: Directory) -> Result<u64, FatError> {
+        // bytes in the clusters of the files under `dir`
+        let cluster_bytes = self.cluster_size_bytes() as u64;
+        let mut total = 0;
+        for entry in self.tree_files(dir)? {
+            total += self.cluster_chain_length(entry.cluster)? as u64 * cluster_bytes;
         }
+        Ok(total)
+    }
+
+    fn tree_files(&mut self, dir: Directory) -> Result<Vec<DirectoryEntry>, FatError> {
+        // file entries of `dir` and its subdirectories
+        let mut files = Vec::new();
+        for item in self.walk(dir) {
+            let item = item?;
+            if !item.entry.is_directory() && !item.entry.is_volume_label() {
+                files.push(item.entry);
+            }
+        }
+        Ok(files)
+    }
+
+    pub fn find_orphan_clusters(&mut self) -> Result<Vec<u32>, FatError> {
+        // first clusters of the chains allocated in the FAT
+        // but not used by any entry reachable from root
+        let mut used = BTreeSet::new();
+        let root = self.root_directory();
+        let mut starts = vec![root.cluster];
+        for item in self.walk(root) {
+            starts.push(item?.entry.cluster);
+        }
+        for start in starts {
+            for cluster in self.chain(start) {
+                match cluster {
+                    Ok(cluster) => used.insert(cluster),
+                    Err(FatError::Io(err)) => return Err(FatError::Io(err)),
+                    // keep what could be followed of corrupted chains
+                    Err(_) => break,
+                };
+            }
+        }
+
+        // orphaned cluster -> next cluster
+        let mut orphans = BTreeMap::new();
+        for cluster in 2..self.total_cluster_count() + 2 {
+            let value = self.fat_lookup(cluster)?;
+            if value != 0 && value != BAD_CLUSTER && !used.contains(&cluster) {
+                orphans.insert(cluster, value);
+            }
+        }
+
+        // chains start with the clusters no other orphan links to,
+        // or, for chains looping on themselves, anywhere in the loop
+        let linked: BTreeSet<u32> = orphans.values().copied().collect();
+        let mut left: Vec<u32> = orphans.keys().copied().collect();
+        left.sort_unstable();
+        let (mut heads, loops): (Vec<u32>, Vec<u32>) = left
+            .into_iter()
+            .partition(|cluster| !linked.contains(cluster));
+        let mut covered = BTreeSet::new();
+        for &head in heads.iter() {
+            let mut cluster = head;
+            while orphans.contains_key(&cluster) && covered.insert(cluster) {
+                cluster = orphans[&cluster];
+            }
+        }
+        for head in loops {
+            if covered.contains(&head) {
+                continue;
+            }
+            heads.push(head);
+            let mut cluster = head;
+            while orphans.contains_key(&cluster) && covered.insert(cluster) {
+                cluster = orphans[&cluster];
+            }
+        }
+        heads.sort_unstable();
+        Ok(heads)
+    }
+
+    pub fn check_integrity(&mut self) -> Result<Vec<IntegrityError>, FatError> {
+        // walk every chain reachable from the root directory,
+        // then look for allocated clusters no chain uses
+        let mut errors = Vec::new();
+        // cluster -> path of the entry using it
+        let mut owners = BTreeMap::new();
+        let cluster_bytes = self.cluster_size_bytes();
+
+        let mut dirs = vec![(self.root_directory(), String::new())];
+        while let Some((dir, path)) = dirs.pop() {
+            let name = if path.is_empty() { "/" } else { &path };
+            if !self
+                .check_chain(dir.cluster, name, &mut owners, &mut errors)?
+                .1
+            {
+                // the entries can't be trusted
+                continue;
+            }
+
+            for entry in self.read_directory_clean(dir)? {
+                let name = entry
+                    .long_name()
+                    .map_or_else(|| entry.full_name(), String::from);
+                let path = format!("{}/{}", path, name);
+                match entry.entry_type() {
+                    EntryType::Dir(dir) => dirs.push((dir, path)),
+                    EntryType::File(file) => {
+                        let (count, valid) =
+                            self.check_chain(file.cluster, &path, &mut owners, &mut errors)?;
+                        if valid && count != file.size.div_ceil(cluster_bytes) {
+                            errors.push(IntegrityError::SizeMismatch {
+                                path,
+                                size: file.size,
+                                clusters: count,
+                            });
+                        }
+                    }
+                }
+            }
+        }
+
+        let free = self.free_space_map()?;
+        for cluster in 2..self.total_cluster_count() + 2 {
+            if free.is_free(cluster) || owners.contains_key(&cluster) {
+                continue;
+            }
+            if self.fat_lookup(cluster)? != BAD_CLUSTER {
+                errors.push(IntegrityError::OrphanedCluster(cluster));
+            }
+        }
+        Ok(errors)
+    }
+
+    fn check_chain(
+        &mut self,
+        start: u32,
+        path: &str,
+        owners: &mut BTreeMap<u32, String>,
+        errors: &mut Vec<IntegrityError>,
+    ) -> Result<(u32, bool), FatError> {
+        // number of clusters of the chain, and whether
+        // it could be followed up to its end
+        let mut count = 0;
+        let mut last = None;
+        let mut chain = self.chain(start);
+        while let Some(cluster) = chain.next() {
+            let error = match cluster {
+                Ok(cluster) => {
+                    if let Some(other) = owners.get(&cluster) {
+                        errors.push(IntegrityError::CrossLinked {
+                            path: path.to_string(),
+                            other: other.clone(),
+                            cluster,
+                        });
+                        return Ok((count, false));
+                    }
+                    owners.insert(cluster, path.to_string());
+                    last = Some(cluster);
+                    count += 1;
+                    continue;
+                }
+                Err(FatError::CyclicFatChain(cluster)) => IntegrityError::CyclicChain {
+                    path: path.to_string(),
+                    cluster,
+                },
+                Err(FatError::BadCluster(cluster)) => {
+                    owners.insert(cluster, path.to_string());
+                    IntegrityError::BadCluster {
+                        path: path.to_string(),
+                        cluster,
+                    }
+                }
+                Err(FatError::InvalidCluster { cluster, .. }) => {
+                    // the link to the invalid cluster is
+                    // still part of the chain
+                    let fs = &mut *chain.fs;
+                    let link = match last {
+                        Some(last) => fs.fat_lookup(last)?,
+                        None => start,
+                    };
+                    owners.insert(link, path.to_string());
+                    IntegrityError::InvalidCluster {
+                        path: path.to_string(),
+                        cluster,
+                    }
+                }
+                Err(err) => return Err(err),
+            };
+            errors.push(error);
+            return Ok((count, false));
+        }
+        Ok((count, true))
+    }
+
+    fn find_by_name(&mut self, dir: Directory, name: &str) -> Result<DirectoryEntry, FatError> {
+        self.find_entry(dir, name)?.ok_or(FatError::NotFound)
+    }
+
+    pub fn open_file(&mut self, file: File) -> FileReader<'_, B, M> {
+        // streaming alternative to read_file
+        FileReader {
+            fs: self,
+            first_cluster: file.cluster,
+            size: file.size,
+            cluster: file.cluster,
+            offset: 0,
+            remaining: file.size,
+            pos: 0,
+        }
+    }
+}
+
+impl<B: Read + Write + Seek> FAT32<B, ReadWrite> {
+    pub fn from_reader_rw(file: B) -> Result<FAT32<B, ReadWrite>, FatError> {
+        // same as from_reader, but allows modifying the image
+        let mut fs = FAT32::from_backend(file)?;
+        fs.write_back = Some(FAT32::flush);
+        Ok(fs)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        // write the modified sectors by increasing
+        // number (the map order), seeking in a single direction
+        while let Some((&sector, data)) = self.dirty.iter().next() {
+            self.file
+                .seek(io::SeekFrom::Start(sector as u64 * self.sector_size as u64))?;
+            self.file.write_all(data)?;
+            self.dirty.remove(&sector);
+        }
+        self.file.flush()
+    }
+
+    fn write_at(&mut self, offset: u64, data: &[u8]) -> Result<(), FatError> {
+        // modify the sectors in memory, they
+        // reach the image on the next flush
+        let sector_size = self.sector_size as u64;
+        let end = offset + data.len() as u64;
+        let mut sector = offset / sector_size;
+        while sector * sector_size < end {
+            let base = sector * sector_size;
+            let start = cmp::max(offset, base);
+            let stop = cmp::min(end, base + sector_size);
+            let number = sector as u32;
+
+            let mut buf = match self.dirty.remove(&number) {
+                Some(buf) => buf,
+                None => {
+                    // sectors only partly overwritten
+                    // keep the rest of their content
+                    let mut buf = vec![0u8; sector_size as usize];
+                    if stop - start < sector_size {
+                        self.file.seek(io::SeekFrom::Start(base))?;
+                        self.file.read_exact(&mut buf)?;
+                    }
+                    buf
+                }
+            };
+            buf[(start - base) as usize..(stop - base) as usize]
+                .copy_from_slice(&data[(start - offset) as usize..(stop - offset) as usize]);
+            self.dirty.insert(number, buf);
+            // may be a FAT sector
+            self.cache.invalidate(number);
+            sector += 1;
+        }
+        Ok(())
+    }
+
+    pub fn format(
+        mut writer: B,
+        label: &str,
+        size_bytes: u64,
+    ) -> Result<FAT32<B, ReadWrite>, FatError> {
+        use byteorder::{ByteOrder, LittleEndian};
+
+        // new empty volume of `size_bytes` bytes, 512 bytes
+        // sectors, 2 FATs and 32 reserved sectors
+        let label = match volume_label(label)? {
+            label if label == [b' '; 11] => *b"NO NAME    ",
+            label => label,
+        };
+        if size_bytes / 512 > u32::MAX as u64 {
+            return Err(FatError::FileTooLarge);
+        }
+        let sector_count = (size_bytes / 512) as u32;
+        let (reserved_sectors, fat_count) = (32, 2);
+        let (cluster_size, fat_size) = format_geometry(sector_count)?;
+        let data_start = reserved_sectors + fat_count * fat_size;
+        let cluster_count = (sector_count - data_start) / cluster_size;
+
+        // boot sector, with a backup copy in sector 6
+        let mut boot = [0u8; 512];
+        boot[..11].copy_from_slice(b"\xeb\x58\x90FAT-RS  ");
+        LittleEndian::write_u16(&mut boot[11..], 512);
+        boot[13] = cluster_size as u8;
+        LittleEndian::write_u16(&mut boot[14..], reserved_sectors as u16);
+        boot[16] = fat_count as u8;
+        // media type: fixed disk
+        boot[21] = 0xf8;
+        LittleEndian::write_u16(&mut boot[24..], 63);
+        LittleEndian::write_u16(&mut boot[26..], 255);
+        LittleEndian::write_u32(&mut boot[32..], sector_count);
+        LittleEndian::write_u32(&mut boot[36..], fat_size);
+        LittleEndian::write_u32(&mut boot[44..], 2);
+        LittleEndian::write_u16(&mut boot[48..], 1);
+        LittleEndian::write_u16(&mut boot[50..], 6);
+        boot[64] = 0x80;
+        boot[66] = 0x29;
+        boot[71..82].copy_from_slice(&label);
+        boot[82..90].copy_from_slice(b"FAT32   ");
+        boot[510..].copy_from_slice(&[0x55, 0xaa]);
+
+        // FSInfo, everything but the root directory is free
+        let mut fs_info = [0u8; 512];
+        LittleEndian::write_u32(&mut fs_info[0..], 0x41615252);
+        LittleEndian::write_u32(&mut fs_info[484..], 0x61417272);
+        LittleEndian::write_u32(&mut fs_info[488..], cluster_count - 1);
+        LittleEndian::write_u32(&mut fs_info[492..], 3);
+        LittleEndian::write_u32(&mut fs_info[508..], 0xaa550000);
+
+        let mut reserved = vec![0u8; (reserved_sectors * 512) as usize];
+        for sector in [0, 6] {
+            reserved[sector * 512..][..512].copy_from_slice(&boot);
+            reserved[(sector + 1) * 512..][..512].copy_from_slice(&fs_info);
+        }
+        writer.seek(io::SeekFrom::Start(0))?;
+        writer.write_all(&reserved)?;
+
+        // FATs: media type and end of chain in the reserved
+        // entries 0 and 1, root directory in cluster 2, and
+        // zeros up to the empty root directory cluster
+        let mut first = [0u8; 512];
+        LittleEndian::write_u32(&mut first[0..], 0x0fffff00 | 0xf8);
+        LittleEndian::write_u32(&mut first[4..], 0x0fffffff);
+        LittleEndian::write_u32(&mut first[8..], 0x0fffffff);
+        let zeros = [0u8; 512];
+        for _ in 0..fat_count {
+            writer.write_all(&first)?;
+            for _ in 1..fat_size {
+                writer.write_all(&zeros)?;
+            }
+        }
+        for _ in 0..cluster_size {
+            writer.write_all(&zeros)?;
+        }
+
+        // last sector, so the image has its full size
+        writer.seek(io::SeekFrom::Start((sector_count - 1) as u64 * 512))?;
+        writer.write_all(&[0u8; 512])?;
+        writer.flush()?;
+
+        FAT32::from_reader_rw(writer)
+    }
+
+    fn fat_write(&mut self, cluster: u32, value: u32) -> Result<(), FatError> {
+        use byteorder::{ByteOrder, LittleEndian};
+
+        // the 4 high bits of a FAT32 entry must be preserved
+        let offset = cluster << 2;
+        let seek = self.fat_copy_offset(self.active_fat()) + offset as u64;
+        let mut raw = [0u8; 4];
+        self.read_at(seek, &mut raw)?;
+        let value = (LittleEndian::read_u32(&raw) & 0xf0000000) | value;
+        LittleEndian::write_u32(&mut raw, value);
+
+        // write to every FAT when mirroring is enabled,
+        // only to the active one otherwise
+        let fats = if self.ext_flags.mirroring_disabled {
+            self.active_fat()..self.active_fat() + 1
+        } else {
+            0..self.fat_count
+        };
+        for fat in fats {
+            let seek = self.fat_copy_offset(fat) + offset as u64;
+            self.write_at(seek, &raw)?;
+        }
+        Ok(())
+    }
+
+    fn allocate_clusters(&mut self, count: u32) -> Result<Vec<u32>, FatError> {
+        use byteorder::{ByteOrder, LittleEndian};
+
+        // find `count` free clusters, scanning the active FAT
+        // one sector at a time, before modifying anything
+        let mut clusters = Vec::with_capacity(count as usize);
+        let max = self.total_cluster_count() + 2;
+        let per_sector = self.sector_size >> 2;
+        let mut sector = vec![0u8; self.sector_size as usize];
+        let mut cluster = 2;
+
+        while (clusters.len() as u32) < count && cluster < max {
+            if cluster == 2 || cluster % per_sector == 0 {
+                let seek = self.fat_copy_offset(self.active_fat()) + ((cluster as u64) << 2);
+                let seek = seek - seek % self.sector_size as u64;
+                self.read_at(seek, &mut sector)?;
+            }
+            let offset = ((cluster % per_sector) << 2) as usize;
+            if LittleEndian::read_u32(&sector[offset..]) & 0x0fffffff == 0 {
+                clusters.push(cluster);
+            }
+            cluster += 1;
+        }
+        if (clusters.len() as u32) < count {
+            return Err(FatError::DiskFull);
+        }
+        self.link_clusters(&clusters)?;
+        Ok(clusters)
+    }
+
+    fn allocate_contiguous(&mut self, count: u32) -> Result<Vec<u32>, FatError> {
+        // a single run of clusters when there is one,
+        // so that new files are not fragmented
+        if count == 0 {
+            return Ok(Vec::new());
+        }
+        match self.free_space_map()?.find_contiguous(count) {
+            Some(start) => {
+                let clusters: Vec<u32> = (start..start + count).collect();
+                self.link_clusters(&clusters)?;
+                Ok(clusters)
+            }
+            None => self.allocate_clusters(count),
+        }
+    }
+
+    fn link_clusters(&mut self, clusters: &[u32]) -> Result<(), FatError> {
+        // chain free clusters together, in order
+        for pair in clusters.windows(2) {
+            self.fat_write(pair[0], pair[1])?;
+        }
+        if let Some(&last) = clusters.last() {
+            self.fat_write(last, 0x0fffffff)?;
+        }
+        let next_free = clusters.last().map(|&last| last + 1);
+        self.update_fsinfo(-(clusters.len() as i64), next_free)
+    }
+
+    fn update_fsinfo(&mut self, delta: i64, next_free: Option<u32>) -> Result<(), FatError> {
+        // keep the free cluster count of FSInfo up to date,
+        // unless the sector is missing or the count unknown,
+        // and move the free cluster hint after an allocation
+        let mut info = match self.read_fsinfo() {
+            Ok(info) => info,
+            Err(FatError::InvalidBootRecord(_)) => return Ok(()),
+            Err(err) => return Err(err),
+        };
+        if info.free_clusters != UNKNOWN_FREE_COUNT {
+            info.free_clusters = cmp::max(info.free_clusters as i64 + delta, 0) as u32;
+        }
+        if let Some(next_free) = next_free {
+            info.next_free = next_free;
+        }
+        self.write_fsinfo(&info)
+    }
+
+    pub fn write_fsinfo(&mut self, info: &FsInfo) -> Result<(), FatError> {
+        use byteorder::{ByteOrder, LittleEndian};
+
+        // only the counters, signatures are left untouched
+        let start = self.fsinfo_start()?;
+        let mut raw = [0u8; 8];
+        LittleEndian::write_u32(&mut raw[0..], info.free_clusters);
+        LittleEndian::write_u32(&mut raw[4..], info.next_free);
+        self.write_at(start + 488, &raw)
+    }
+
+    pub fn write_sector(&mut self, sector: u32, data: &[u8]) -> Result<(), FatError> {
+        // `data` must be exactly one sector long
+        self.check_sector(sector)?;
+        if data.len() != self.sector_size as usize {
+            return Err(FatError::InvalidOperation);
+        }
+        self.write_at(sector as u64 * self.sector_size as u64, data)
+    }
+
+    pub fn write_sector_direct(&mut self, sector: u32, data: &[u8]) -> Result<(), FatError> {
+        // same as write_sector, but written to the
+        // image right away instead of on flush
+        self.check_sector(sector)?;
+        if data.len() != self.sector_size as usize {
+            return Err(FatError::InvalidOperation);
+        }
+        self.dirty.remove(&sector);
+        self.file
+            .seek(io::SeekFrom::Start(sector as u64 * self.sector_size as u64))?;
+        self.file.write_all(data)?;
+        self.file.flush()?;
+        self.cache.invalidate(sector);
+        Ok(())
+    }
+
+    pub fn write_cluster(&mut self, cluster: u32, data: &[u8]) -> Result<(), FatError> {
+        // replaces the content of a data cluster,
+        // `data` must be exactly one cluster long
+        self.check_cluster(cluster)?;
+        if data.len() != self.cluster_size_bytes() as usize {
+            return Err(FatError::InvalidOperation);
+        }
+        let seek = self.cluster_offset(cluster)?;
+        self.write_at(seek, data)
+    }
+
+    fn zero_cluster(&mut self, cluster: u32) -> Result<(), FatError> {
+        let seek = self.cluster_offset(cluster)?;
+        self.write_at(seek, &vec![0u8; self.cluster_size_bytes() as usize])
+    }
+
+    pub fn wipe_free_space(&mut self) -> Result<u64, FatError> {
+        self.wipe_free_space_with_progress(|_, _| {})
+    }
+
+    pub fn wipe_free_space_with_progress(
+        &mut self,
+        mut progress: impl FnMut(u32, u32),
+    ) -> Result<u64, FatError> {
+        // zeroes the data of every free cluster so that deleted
+        // content can't be recovered, the FAT is left untouched;
+        // `progress` gets the wiped and total free cluster counts
+        let map = self.free_space_map()?;
+        let total = map.free_count();
+        let mut wiped = 0;
+        for cluster in (2..self.total_cluster_count() + 2).filter(|&c| map.is_free(c)) {
+            self.zero_cluster(cluster)?;
+            wiped += 1;
+            progress(wiped, total);
+        }
+        Ok(wiped as u64 * self.cluster_size_bytes() as u64)
+    }
+
+    fn free_entry_offset(&mut self, dir: Directory) -> Result<u64, FatError> {
+        // byte offset of the first unused entry of `dir`,
+        // a new cluster is added to the directory if it is full
+        let count = self.cluster_size_bytes() >> 5;
+        let clusters = self.chain_to_vec(dir.cluster)?;
+
+        for &cluster in clusters.iter() {
+            let start = self.cluster_offset(cluster)?;
+            for i in 0..count as u64 {
+                let mut first = [0u8];
+                self.read_at(start + (i << 5), &mut first)?;
+                // end marker or deleted entry
+                if first[0] == 0 || first[0] == 0xe5 {
+                    return Ok(start + (i << 5));
+                }
+            }
+        }
+
+        let new = self.expand_directory(dir)?;
+        self.cluster_offset(new)
+    }
+
+    pub fn expand_directory(&mut self, dir: Directory) -> Result<u32, FatError> {
+        // link a zeroed cluster at the end of `dir`, its
+        // first entry is the end marker of the directory
+        let last = self.chain_to_vec(dir.cluster)?.last().copied();
+        let new = self.allocate_clusters(1)?[0];
+        self.zero_cluster(new)?;
+        if let Some(last) = last {
+            self.fat_write(last, new)?;
+        }
+        Ok(new)
+    }
+
+    fn write_clusters(&mut self, clusters: &[u32], data: &[u8]) -> Result<(), FatError> {
+        // write `data` across `clusters`, padding the last one with zeros
+        let cluster_size = self.cluster_size_bytes() as usize;
+        for (&cluster, chunk) in clusters.iter().zip(data.chunks(cluster_size)) {
+            let seek = self.cluster_offset(cluster)?;
+            self.write_at(seek, chunk)?;
+            self.write_at(
+                seek + chunk.len() as u64,
+                &vec![0u8; cluster_size - chunk.len()],
+            )?;
+        }
+        Ok(())
+    }
+
+    fn new_entry_name(&mut self, dir: Directory, name: &str) -> Result<[u8; 11], FatError> {
+        // 8.3 form of `name`, which must not already be used in `dir`
+        let short = short_name(name)?;
+        if self
+            .read_directory(dir)?
+            .iter()
+            .any(|entry| entry.has_name(name) || entry.name.eq_ignore_ascii_case(&short))
+        {
+            return Err(FatError::AlreadyExists);
+        }
+        Ok(short)
+    }
+
+    fn write_entry(
+        &mut self,
+        offset: u64,
+        name: &[u8; 11],
+        attributes: FileAttributes,
+        cluster: u32,
+        size: u32,
+    ) -> Result<(), FatError> {
+        use byteorder::{ByteOrder, LittleEndian};
+
+        // 32 bytes entry, times and dates are left to 0
+        let mut raw = [0u8; 32];
+        raw[..11].copy_from_slice(name);
+        raw[11] = attributes.bits();
+        LittleEndian::write_u16(&mut raw[20..], (cluster >> 16) as u16);
+        LittleEndian::write_u16(&mut raw[26..], cluster as u16);
+        LittleEndian::write_u32(&mut raw[28..], size);
+        self.write_at(offset, &raw)
+    }
+
+    fn set_entry_cluster(&mut self, offset: u64, cluster: u32) -> Result<(), FatError> {
+        use byteorder::{ByteOrder, LittleEndian};
+
+        // first cluster of the entry at `offset`,
+        // split in two 16 bits words
+        let mut raw = [0u8; 2];
+        LittleEndian::write_u16(&mut raw, (cluster >> 16) as u16);
+        self.write_at(offset + 20, &raw)?;
+        LittleEndian::write_u16(&mut raw, cluster as u16);
+        self.write_at(offset + 26, &raw)
+    }
+
+    pub fn write_file(
+        &mut self,
+        dir: Directory,
+        name: &str,
+        data: &[u8],
+    ) -> Result<File, FatError> {
+        // file size is stored on 32 bits
+        if data.len() as u64 > u32::MAX as u64 {
+            return Err(FatError::FileTooLarge);
+        }
+        let size = data.len() as u32;
+        let name = self.new_entry_name(dir, name)?;
+
+        // reserve the directory entry first, growing
+        // the directory can take a cluster as well
+        let entry = self.free_entry_offset(dir)?;
+
+        // empty files have no cluster at all
+        let cluster_size = self.cluster_size_bytes() as usize;
+        let count = data.len().div_ceil(cluster_size);
+        let clusters = self.allocate_contiguous(count as u32)?;
+        self.write_clusters(&clusters, data)?;
+        let cluster = clusters.first().cloned().unwrap_or(0);
+
+        // archive flag: file is new
+        self.write_entry(entry, &name, FileAttributes::ARCHIVE, cluster, size)?;
+
+        Ok(File { cluster, size })
+    }
+
+    pub fn pre_allocate(
+        &mut self,
+        dir: Directory,
+        name: &str,
+        size: u64,
+    ) -> Result<File, FatError> {
+        // reserves the clusters of a file of `size` bytes, in one run
+        // when possible, without writing them: the entry starts empty
+        // and append_to_file fills the chain before extending it
+        if size > u32::MAX as u64 {
+            return Err(FatError::FileTooLarge);
+        }
+        let name = self.new_entry_name(dir, name)?;
+        let entry = self.free_entry_offset(dir)?;
+
+        let count = size.div_ceil(self.cluster_size_bytes() as u64);
+        let clusters = self.allocate_contiguous(count as u32)?;
+        let cluster = clusters.first().cloned().unwrap_or(0);
+        self.write_entry(entry, &name, FileAttributes::ARCHIVE, cluster, 0)?;
+
+        Ok(File { cluster, size: 0 })
+    }
+
+    pub fn create_file(
+        &mut self,
+        dir: Directory,
+        name: &str,
+    ) -> Result<FileWriter<'_, B>, FatError> {
+        // empty file written with the returned FileWriter,
+        // its size is updated on flush
+        let name = self.new_entry_name(dir, name)?;
+        let entry = self.free_entry_offset(dir)?;
+        let cluster = self.allocate_clusters(1)?[0];
+        self.write_entry(entry, &name, FileAttributes::ARCHIVE, cluster, 0)?;
+        Ok(FileWriter {
+            fs: self,
+            entry,
+            cluster,
+            offset: 0,
+            size: 0,
+        })
+    }
+
+    pub fn create_directory(
+        &mut self,
+        parent: Directory,
+        name: &str,
+    ) -> Result<Directory, FatError> {
+        let name = self.new_entry_name(parent, name)?;
+        let entry = self.free_entry_offset(parent)?;
+
+        // a single empty cluster, marked as end of chain
+        let cluster = self.allocate_clusters(1)?[0];
+        self.zero_cluster(cluster)?;
+
+        // . and .. entries, .. is 0 when the parent is root
+        let start = self.cluster_offset(cluster)?;
+        let parent_cluster = if parent.cluster == self.root_dir {
+            0
+        } else {
+            parent.cluster
+        };
+        let dir = FileAttributes::DIRECTORY;
+        self.write_entry(start, b".          ", dir, cluster, 0)?;
+        self.write_entry(start + 32, b"..         ", dir, parent_cluster, 0)?;
+
+        self.write_entry(entry, &name, dir, cluster, 0)?;
+
+        Ok(Directory { cluster })
+    }
+
+    fn free_chain(&mut self, start: u32) -> Result<(), FatError> {
+        // the whole chain is read first, nothing
+        // is freed if it turns out to be corrupt
+        let clusters = self.chain_to_vec(start)?;
+        self.free_clusters(&clusters)
+    }
+
+    fn free_clusters(&mut self, clusters: &[u32]) -> Result<(), FatError> {
+        for &cluster in clusters.iter() {
+            self.fat_write(cluster, 0)?;
+        }
+        self.update_fsinfo(clusters.len() as i64, None)
+    }
+
+    pub fn restore_boot_sector_from_backup(&mut self) -> Result<(), FatError> {
+        // the whole backup sector is copied, once its
+        // BIOS Parameter Block has been checked
+        let boot = self.backup_boot_sector()?;
+        let mut sector = vec![0u8; self.sector_size as usize];
+        self.read_at(self.backup_boot_offset()?, &mut sector)?;
+        self.write_at(0, &sector)?;
+
+        // the handle then works from the restored values, once
+        // what was buffered with the old ones is written out
+        self.flush()?;
+        self.cache.sectors.clear();
+        self.sector_size = boot.bytes_per_sector as u32;
+        self.cluster_size = boot.sectors_per_cluster as u32;
+        self.reserved_sectors = boot.reserved_sectors as u32;
+        self.fat_count = boot.num_fats as u32;
+        self.sector_count = boot.total_sectors;
+        self.fat_size = boot.fat_size;
+        self.ext_flags = ExtendedFlags::from(boot.ext_flags);
+        self.root_dir = boot.root_cluster;
+        self.fs_info = boot.fs_info_sector as u32;
+        self.boot = boot;
+        Ok(())
+    }
+
+    pub fn set_volume_label(&mut self, label: &str) -> Result<(), FatError> {
+        // the label is both in the boot record and
+        // in an entry of the root directory
+        let padded = volume_label(label)?;
+        let root = self.root_directory();
+        let entry = self.volume_label_entry()?;
+
+        let boot_label = if label.is_empty() { [0u8; 11] } else { padded };
+        self.write_at(71, &boot_label)?;
+        // and in its copy, when the volume has one
+        if let Ok(backup) = self.backup_boot_offset() {
+            self.write_at(backup + 71, &boot_label)?;
+        }
+        self.boot.volume_label = boot_label;
+
+        match entry {
+            Some(entry) if label.is_empty() => self.write_at(entry.offset, &[0xe5])?,
+            Some(entry) => self.write_at(entry.offset, &padded)?,
+            None if label.is_empty() => (),
+            None => {
+                let offset = self.free_entry_offset(root)?;
+                self.write_entry(offset, &padded, FileAttributes::VOLUME_LABEL, 0, 0)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn set_file_attributes(
+        &mut self,
+        path: &str,
+        attrs: FileAttributes,
+    ) -> Result<(), FatError> {
+        // only the attribute byte of the entry is written, the
+        // directory bit of the entry is kept as it is
+        if attrs.intersects(FileAttributes::DIRECTORY | FileAttributes::VOLUME_LABEL) {
+            return Err(FatError::InvalidOperation);
+        }
+        let entry = self.path_entry(path)?.ok_or(FatError::InvalidOperation)?;
+        let attrs = attrs | (entry.attributes() & FileAttributes::DIRECTORY);
+        self.write_at(entry.offset + 11, &[attrs.bits()])?;
+        // on the image right away, as a chmod would be
+        self.flush()?;
+        Ok(())
+    }
+
+    pub fn set_file_timestamps(
+        &mut self,
+        path: &str,
+        created: Timestamp,
+        modified: Timestamp,
+    ) -> Result<(), FatError> {
+        use byteorder::{ByteOrder, LittleEndian};
+
+        // creation time with its 10 ms units at offset 13,
+        // last write time and date at offset 22
+        if !created.fits_fat() || !modified.fits_fat() {
+            return Err(FatError::InvalidTimestamp);
+        }
+        let entry = self.path_entry(path)?.ok_or(FatError::InvalidOperation)?;
+        let mut raw = [0u8; 5];
+        let (date, time, tenths) = created.to_fat_date_time();
+        raw[0] = tenths;
+        LittleEndian::write_u16(&mut raw[1..], time);
+        LittleEndian::write_u16(&mut raw[3..], date);
+        self.write_at(entry.offset + 13, &raw)?;
+
+        let mut raw = [0u8; 4];
+        let (date, time, _) = modified.to_fat_date_time();
+        LittleEndian::write_u16(&mut raw, time);
+        LittleEndian::write_u16(&mut raw[2..], date);
+        self.write_at(entry.offset + 22, &raw)?;
+        // on the image right away, as set_file_attributes
+        self.flush()?;
+        Ok(())
+    }
+
+    fn mark_deleted(&mut self, entry: &DirectoryEntry) -> Result<(), FatError> {
+        // 0xe5 as first byte marks deleted entries,
+        // including the ones holding the long name
+        let offsets = entry.long_name_offsets.iter().chain(Some(&entry.offset));
+        for &offset in offsets {
+            self.write_at(offset, &[0xe5])?;
+        }
+        Ok(())
+    }
+
+    pub fn delete_file(&mut self, dir: Directory, name: &str) -> Result<(), FatError> {
+        let entry = self.find_by_name(dir, name)?;
+        if entry.is_directory() {
+            return Err(FatError::IsDirectory);
+        }
+
+        // empty files have no cluster; a corrupt chain fails
+        // here, before the entry owning it is gone
+        if entry.cluster != 0 {
+            self.free_chain(entry.cluster)?;
+        }
+        self.mark_deleted(&entry)
+    }
+
+    pub fn secure_delete(
+        &mut self,
+        path: &str,
+        options: SecureDeleteOptions,
+    ) -> Result<(), FatError> {
+        // overwrites the clusters of the file before deleting it,
+        // each pass is flushed so that it actually reaches the
+        // image instead of being replaced by the next one
+        let (dir, name) = self.parent_directory(path)?;
+        let entry = self.find_by_name(dir, name)?;
+        if entry.is_directory() {
+            return Err(FatError::IsDirectory);
+        }
+        let clusters = self.chain_to_vec(entry.cluster)?;
+        let cluster_size = self.cluster_size_bytes() as usize;
+
+        for pass in 0..options.passes {
+            // the pattern and its complement alternate,
+            // ending with the pattern itself
+            let fill = match (options.passes - 1 - pass) % 2 {
+                0 => options.pattern,
+                _ => !options.pattern,
+            };
+            let mut remaining = entry.size as usize;
+            for &cluster in clusters.iter() {
+                let start = self.cluster_offset(cluster)?;
+                let used = cmp::min(remaining, cluster_size);
+                remaining -= used;
+                self.write_at(start, &vec![fill; used])?;
+
+                // the slack past the end of the file is
+                // only written when it holds something else
+                let mut slack = vec![0u8; cluster_size - used];
+                self.read_at(start + used as u64, &mut slack)?;
+                if slack.iter().any(|&byte| byte != fill) {
+                    self.write_at(start + used as u64, &vec![fill; slack.len()])?;
+                }
+            }
+            self.flush()?;
+        }
+
+        self.delete_file(dir, name)?;
+        self.flush()?;
+        Ok(())
+    }
+
+    pub fn copy_file(&mut self, src: &str, dst: &str) -> Result<(), FatError> {
+        // copy of the file `src` to the new path `dst`
+        let file = match self.open_path(src)? {
+            EntryType::File(file) => file,
+            EntryType::Dir(_) => return Err(FatError::IsDirectory),
+        };
+        let data = self.read_file(file)?;
+        let (dir, name) = self.parent_directory(dst)?;
+        self.write_file(dir, name, &data)?;
+        Ok(())
+    }
+
+    pub fn move_file(&mut self, src: &str, dst: &str) -> Result<(), FatError> {
+        // new entry for the same clusters in the directory
+        // of `dst`, the FAT is left as is
+        let (src_dir, src_name) = self.parent_directory(src)?;
+        let entry = self.find_by_name(src_dir, src_name)?;
+        if entry.is_directory() {
+            return Err(FatError::IsDirectory);
+        }
+        let (dst_dir, dst_name) = self.parent_directory(dst)?;
+        if src_dir == dst_dir {
+            return self.rename_entry(src_dir, src_name, dst_name);
+        }
+        let name = self.new_entry_name(dst_dir, dst_name)?;
+
+        // copy of the entry, keeping its dates and attributes,
+        // written before the old one is removed
+        let mut raw = [0u8; 32];
+        self.read_at(entry.offset, &mut raw)?;
+        raw[..11].copy_from_slice(&name);
+        let offset = self.free_entry_offset(dst_dir)?;
+        self.write_at(offset, &raw)?;
+
+        self.mark_deleted(&entry)
+    }
+
+    pub fn recover_file(
+        &mut self,
+        deleted: DirectoryEntry,
+        new_name: &str,
+        dest_dir: Directory,
+    ) -> Result<File, FatError> {
+        // undelete a file from read_directory_with_deleted: its chain
+        // was erased, so its clusters are assumed to be contiguous
+        if !deleted.is_deleted() {
+            return Err(FatError::InvalidOperation);
+        }
+        if deleted.is_directory() {
+            return Err(FatError::IsDirectory);
+        }
+        let name = self.new_entry_name(dest_dir, new_name)?;
+
+        let count = deleted.size.div_ceil(self.cluster_size_bytes());
+        let cluster = if count == 0 { 0 } else { deleted.cluster };
+        if count > 0 {
+            self.check_cluster(cluster)?;
+            self.check_cluster(cluster + count - 1)?;
+            for c in cluster..cluster + count {
+                if self.fat_lookup(c)? != 0 {
+                    return Err(FatError::ClusterOverwritten(c));
+                }
+            }
+            for c in cluster..cluster + count - 1 {
+                self.fat_write(c, c + 1)?;
+            }
+            self.fat_write(cluster + count - 1, 0x0fffffff)?;
+            self.update_fsinfo(-(count as i64), None)?;
+        }
+
+        let offset = self.free_entry_offset(dest_dir)?;
+        self.write_entry(offset, &name, deleted.attributes(), cluster, deleted.size)?;
+        Ok(File {
+            cluster,
+            size: deleted.size,
+        })
+    }
+
+    pub fn rename_entry(
+        &mut self,
+        dir: Directory,
+        old_name: &str,
+        new_name: &str,
+    ) -> Result<(), FatError> {
+        let entry = self.find_by_name(dir, old_name)?;
+        if short_name(new_name)? == entry.name {
+            return Ok(());
+        }
+        let name = self.new_entry_name(dir, new_name)?;
+
+        // the long name would not match the new
+        // name anymore, drop its entries
+        for &offset in entry.long_name_offsets.iter() {
+            self.write_at(offset, &[0xe5])?;
+        }
+
+        self.write_at(entry.offset, &name)
+    }
+
+    pub fn truncate_file(
+        &mut self,
+        dir: Directory,
+        name: &str,
+        new_size: u32,
+    ) -> Result<(), FatError> {
+        use byteorder::{ByteOrder, LittleEndian};
+
+        let entry = self.find_by_name(dir, name)?;
+        if entry.is_directory() {
+            return Err(FatError::IsDirectory);
+        }
+        if new_size > entry.size {
+            return Err(FatError::InvalidOperation);
+        }
+
+        if new_size == 0 {
+            // no cluster at all for empty files
+            if entry.cluster != 0 {
+                self.free_chain(entry.cluster)?;
+            }
+            self.set_entry_cluster(entry.offset, 0)?;
+        } else {
+            // the cluster holding the last byte
+            // becomes the end of the chain
+            let keep = new_size.div_ceil(self.cluster_size_bytes()) as usize;
+            let clusters = self.chain_to_vec(entry.cluster)?;
+            if clusters.len() < keep {
+                return Err(FatError::UnexpectedEof);
+            }
+            self.fat_write(clusters[keep - 1], 0x0fffffff)?;
+            self.free_clusters(&clusters[keep..])?;
+        }
+
+        let mut raw = [0u8; 4];
+        LittleEndian::write_u32(&mut raw, new_size);
+        self.write_at(entry.offset + 28, &raw)
+    }
+
+    pub fn append_to_file(
+        &mut self,
+        dir: Directory,
+        name: &str,
+        data: &[u8],
+    ) -> Result<(), FatError> {
+        use byteorder::{ByteOrder, LittleEndian};
+
+        let entry = self.find_by_name(dir, name)?;
+        if entry.is_directory() {
+            return Err(FatError::IsDirectory);
+        }
+        let new_size = (entry.size as u64) + data.len() as u64;
+        if new_size > u32::MAX as u64 {
+            return Err(FatError::FileTooLarge);
+        }
+
+        // index of the cluster where the next byte belongs,
+        // it is past the end of the chain if the last
+        // cluster is full
+        let cluster_size = self.cluster_size_bytes();
+        let clusters = self.chain_to_vec(entry.cluster)?;
+        let index = (entry.size / cluster_size) as usize;
+        if clusters.len() < index {
+            return Err(FatError::UnexpectedEof);
+        }
+
+        // fill the clusters already in the chain
+        let mut offset = entry.size % cluster_size;
+        let mut data = data;
+        for &cluster in clusters[index..].iter() {
+            if data.is_empty() {
+                break;
+            }
+            let len = cmp::min((cluster_size - offset) as usize, data.len());
+            let seek = self.cluster_offset(cluster)? + offset as u64;
+            self.write_at(seek, &data[..len])?;
+            data = &data[len..];
+            offset = 0;
+        }
+
+        // then link new ones for what is left
+        if !data.is_empty() {
+            let count = data.len().div_ceil(cluster_size as usize);
+            let added = self.allocate_clusters(count as u32)?;
+            self.write_clusters(&added, data)?;
+            if let Some(&last) = clusters.last() {
+                self.fat_write(last, added[0])?;
+            } else {
+                // the file was empty, without any cluster
+                self.set_entry_cluster(entry.offset, added[0])?;
+            }
+        }
+
+        let mut raw = [0u8; 4];
+        LittleEndian::write_u32(&mut raw, new_size as u32);
+        self.write_at(entry.offset + 28, &raw)
+    }
+
+    pub fn defragment_file(&mut self, path: &str) -> Result<DefragReport, FatError> {
+        // copies a fragmented file to a single run of free
+        // clusters, the entry points to the copy before
+        // the old clusters are freed
+        let entry = self.path_entry(path)?.ok_or(FatError::IsDirectory)?;
+        if entry.is_directory() {
+            return Err(FatError::IsDirectory);
+        }
+        let clusters = self.chain_to_vec(entry.cluster)?;
+        let mut report = DefragReport {
+            moved: false,
+            old_first: entry.cluster,
+            new_first: entry.cluster,
+            clusters_moved: 0,
+        };
+        if clusters.windows(2).all(|pair| pair[1] == pair[0] + 1) {
+            return Ok(report);
+        }
+
+        let count = clusters.len() as u32;
+        let start = self
+            .free_space_map()?
+            .find_contiguous(count)
+            .ok_or(FatError::NoContiguousSpace)?;
+        let moved: Vec<u32> = (start..start + count).collect();
+        for (&old, &new) in clusters.iter().zip(moved.iter()) {
+            let data = self.read_cluster(old)?;
+            self.write_cluster(new, &data)?;
+        }
+        self.link_clusters(&moved)?;
+        self.set_entry_cluster(entry.offset, start)?;
+        self.free_clusters(&clusters)?;
+
+        report.moved = true;
+        report.new_first = start;
+        report.clusters_moved = count;
+        Ok(report)
+    }
+
+    #[cfg(feature = "std")]
+    pub fn import_from_directory(
+        &mut self,
+        src: &path::Path,
+        options: ImportOptions,
+    ) -> Result<(), FatError> {
+        // copy a host tree into the root directory; every name
+        // is checked before anything is written, long names
+        // can't be created
+        let mut items = Vec::new();
+        let mut invalid = Vec::new();
+        host_tree(src, path::Path::new(""), options, &mut items, &mut invalid)?;
+        if !invalid.is_empty() {
+            return Err(FatError::InvalidNames(invalid));
+        }
+
+        let mut dirs = BTreeMap::new();
+        dirs.insert(path::PathBuf::new(), self.root_directory());
+        for (relative, is_dir) in items {
+            let parent = dirs[relative.parent().unwrap_or(path::Path::new(""))];
+            let name = relative
+                .file_name()
+                .and_then(|name| name.to_str())
+                .ok_or(FatError::InvalidName)?;
+            let existing = self.find_entry(parent, name)?;
+            if is_dir {
+                let dir = match existing.map(|entry| entry.entry_type()) {
+                    Some(EntryType::Dir(dir)) => dir,
+                    Some(EntryType::File(_)) => return Err(FatError::AlreadyExists),
+                    None => self.create_directory(parent, name)?,
+                };
+                dirs.insert(relative, dir);
+                continue;
+            }
+            if let Some(entry) = existing {
+                if entry.is_directory() {
+                    return Err(FatError::IsDirectory);
+                }
+                if !options.overwrite {
+                    continue;
+                }
+                self.delete_file(parent, name)?;
+            }
+            self.write_file(parent, name, &fs::read(src.join(&relative))?)?;
+        }
+        Ok(())
+    }
+
+    pub fn repair(&mut self) -> Result<RepairReport, FatError> {
+        // same walk as check_integrity, the first entry found
+        // using a cluster keeps it and the others are cut just
+        // before it; what no entry uses anymore is then freed
+        use byteorder::{ByteOrder, LittleEndian};
+
+        let mut report = RepairReport::default();
+        let mut owners = BTreeSet::new();
+        let cluster_bytes = self.cluster_size_bytes() as u64;
+
+        let mut dirs = vec![self.root_directory()];
+        while let Some(dir) = dirs.pop() {
+            let (count, complete) =
+                self.claim_chain(dir.cluster, None, &mut owners, &mut report)?;
+            if count == 0 || !complete {
+                // the entries can't be trusted
+                continue;
+            }
+
+            for entry in self.read_directory_clean(dir)? {
+                match entry.entry_type() {
+                    EntryType::Dir(dir) => dirs.push(dir),
+                    EntryType::File(file) => {
+                        let (count, _) = self.claim_chain(
+                            file.cluster,
+                            Some(entry.offset),
+                            &mut owners,
+                            &mut report,
+                        )?;
+                        let size = count as u64 * cluster_bytes;
+                        if file.size as u64 > size {
+                            let mut raw = [0u8; 4];
+                            LittleEndian::write_u32(&mut raw, size as u32);
+                            self.write_at(entry.offset + 28, &raw)?;
+                            report.sizes_corrected += 1;
+                            report.changes_written += 1;
+                        }
+                    }
+                }
+            }
+        }
+
+        let free = self.free_space_map()?;
+        let mut orphans = Vec::new();
+        for cluster in 2..self.total_cluster_count() + 2 {
+            if free.is_free(cluster) || owners.contains(&cluster) {
+                continue;
+            }
+            if self.fat_lookup(cluster)? != BAD_CLUSTER {
+                orphans.push(cluster);
+            }
+        }
+        self.free_clusters(&orphans)?;
+        report.orphans_freed = orphans.len() as u32;
+        report.changes_written += orphans.len() as u32;
+        Ok(report)
+    }
+
+    fn claim_chain(
+        &mut self,
+        start: u32,
+        entry: Option<u64>,
+        owners: &mut BTreeSet<u32>,
+        report: &mut RepairReport,
+    ) -> Result<(u32, bool), FatError> {
+        // number of clusters left in the chain, and whether
+        // it ends with an end of chain marker; a file cross
+        // linked from its first cluster is left empty
+        let mut clusters = Vec::new();
+        let mut shared = false;
+        let mut complete = true;
+        let mut chain = self.chain(start);
+        while let Some(cluster) = chain.next() {
+            match cluster {
+                Ok(cluster) if owners.contains(&cluster) => {
+                    shared = true;
+                    break;
+                }
+                Ok(cluster) => clusters.push(cluster),
+                Err(FatError::BadCluster(cluster)) => {
+                    clusters.push(cluster);
+                    complete = false;
+                }
+                Err(FatError::InvalidCluster { max, .. }) => {
+                    // the cluster holding the invalid link ends the
+                    // chain, a file whose first cluster doesn't
+                    // exist is left empty
+                    let fs = &mut *chain.fs;
+                    let holder = match clusters.last() {
+                        Some(&last) => fs.fat_lookup(last)?,
+                        None => start,
+                    };
+                    match entry {
+                        _ if (2..=max).contains(&holder) => {
+                            fs.fat_write(holder, 0x0fffffff)?;
+                            clusters.push(holder);
+                        }
+                        Some(offset) => fs.set_entry_cluster(offset, 0)?,
+                        None => return Ok((0, false)),
+                    }
+                    report.invalid_links_cut += 1;
+                    report.changes_written += 1;
+                    complete = false;
+                    break;
+                }
+                Err(FatError::CyclicFatChain(_)) => complete = false,
+                Err(err) => return Err(err),
+            }
+        }
+
+        if shared {
+            match (clusters.last(), entry) {
+                (Some(&last), _) => self.fat_write(last, 0x0fffffff)?,
+                (None, Some(offset)) => self.set_entry_cluster(offset, 0)?,
+                // a directory can't be emptied that way
+                (None, None) => return Ok((0, false)),
+            }
+            report.cross_links_broken += 1;
+            report.changes_written += 1;
+        }
+        owners.extend(clusters.iter().copied());
+        Ok((clusters.len() as u32, complete))
+    }
+}
+
+impl<B, M> Drop for FAT32<B, M> {
+    fn drop(&mut self) {
+        // modified sectors must not be lost silently,
+        // call flush before to handle the error
+        if let Some(write_back) = self.write_back {
+            if let Err(err) = write_back(self) {
+                #[cfg(feature = "std")]
+                if std::thread::panicking() {
+                    return;
+                }
+                panic!("failed to write back modified sectors: {:?}", err);
+            }
+        }
+    }
+}
+
+// least recently used cache of sectors, the
+// most recently used one is the last
+#[derive(Debug)]
+struct SectorCache {
+    capacity: usize,
+    sectors: Vec<(u32, Vec<u8>)>,
+    hits: u64,
+    misses: u64,
+}
+
+impl SectorCache {
+    fn new(capacity: usize) -> SectorCache {
+        SectorCache {
+            capacity,
+            sectors: Vec::with_capacity(capacity),
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    fn get(&mut self, sector: u32) -> Option<&[u8]> {
+        match self.sectors.iter().position(|&(n, _)| n == sector) {
+            Some(index) => {
+                self.hits += 1;
+                let entry = self.sectors.remove(index);
+                self.sectors.push(entry);
+                self.sectors.last().map(|(_, data)| &data[..])
+            }
+            None => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+
+    fn insert(&mut self, sector: u32, data: Vec<u8>) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.sectors.len() == self.capacity {
+            self.sectors.remove(0);
+        }
+        self.sectors.push((sector, data));
+    }
+
+    fn invalidate(&mut self, sector: u32) {
+        self.sectors.retain(|&(n, _)| n != sector);
+    }
+}
+
+// entry found while walking a tree, with its path
+// from the directory where the walk started
+#[derive(Clone, Debug)]
+pub struct PathEntry {
+    pub path: String,
+    pub entry: DirectoryEntry,
+}
+
+// difference found by diff, between the
+// volume it is called on and the other one
+#[derive(Clone, Debug)]
+pub enum DiffEntry {
+    Added(PathEntry),
+    Removed(PathEntry),
+    Modified {
+        path: String,
+        old_size: u32,
+        new_size: u32,
+        content_changed: bool,
+    },
+}
+
+// file contents are only read with `compare_content`,
+// otherwise files of the same size are taken as equal
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DiffOptions {
+    pub compare_content: bool,
+}
+
+// by default extract_to_directory fails with
+// AlreadyExists rather than replace a host file
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ExtractOptions {
+    pub overwrite: bool,
+}
+
+// import_from_directory keeps the files already in the
+// image unless `overwrite`, hidden means a leading `.`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ImportOptions {
+    pub overwrite: bool,
+    pub skip_hidden: bool,
+}
+
+// depth first traversal of a directory tree, the entries of a
+// directory come right after the directory itself
+#[derive(Debug)]
+pub struct WalkIterator<'a, B, M> {
+    fs: &'a mut FAT32<B, M>,
+    // directory to read before going on
+    pending: Option<(String, Directory)>,
+    // path and entries left of each level
+    stack: Vec<(String, vec::IntoIter<DirectoryEntry>)>,
+    // directories already read, a corrupted
+    // tree could otherwise loop forever
+    seen: BTreeSet<u32>,
+}
+
+impl<'a, B: Read + Seek, M> WalkIterator<'a, B, M> {
+    fn new(fs: &'a mut FAT32<B, M>, root: Directory, prefix: String) -> WalkIterator<'a, B, M> {
+        WalkIterator {
+            fs,
+            pending: Some((prefix, root)),
+            stack: Vec::new(),
+            seen: BTreeSet::new(),
+        }
+    }
+}
+
+impl<B: Read + Seek, M> Iterator for WalkIterator<'_, B, M> {
+    type Item = Result<PathEntry, FatError>;
+
+    fn next(&mut self) -> Option<Result<PathEntry, FatError>> {
+        if let Some((path, dir)) = self.pending.take() {
+            if self.seen.insert(dir.cluster) {
+                match self.fs.read_directory(dir) {
+                    Ok(entries) => self.stack.push((path, entries.into_iter())),
+                    Err(err) => return Some(Err(err)),
+                }
+            }
+        }
+
+        loop {
+            let (prefix, entries) = self.stack.last_mut()?;
+            let entry = match entries.next() {
+                Some(entry) => entry,
+                None => {
+                    // level done
+                    self.stack.pop();
+                    continue;
+                }
+            };
+
+            if entry.is_dot_entry() {
+                continue;
+            }
+            // prefer the long file name when there is one
+            let name = entry
+                .long_name()
+                .map_or_else(|| entry.full_name(), String::from);
+            let path = format!("{}/{}", prefix, name);
+            if let EntryType::Dir(dir) = entry.entry_type() {
+                self.pending = Some((path.clone(), dir));
+            }
+            return Some(Ok(PathEntry { path, entry }));
+        }
+    }
+}
+
+// reads the entries of a directory one by one,
+// following the chain of the directory clusters
+#[derive(Debug)]
+pub struct DirectoryIterator<'a, B, M> {
+    chain: FatChain<'a, B, M>,
+    // current cluster, index of the next entry in
+    // this cluster and entries per cluster
+    cluster: u32,
+    index: u32,
+    count: u32,
+    // long file name being collected, attached
+    // to the next regular entry
+    long_name: Option<LongName>,
+    // end marker found or error
+    done: bool,
+    // also return the deleted entries
+    with_deleted: bool,
+    // also return the volume label entry
+    with_labels: bool,
+}
+
+impl<B: Read + Seek, M> DirectoryIterator<'_, B, M> {
+    fn read_entry(&mut self) -> Result<Option<DirectoryEntry>, FatError> {
+        // end of cluster, read next one
+        if self.index == self.count {
+            self.cluster = match self.chain.next() {
+                Some(cluster) => cluster?,
+                None => {
+                    self.done = true;
+                    return Ok(None);
+                }
+            };
+            self.index = 0;
+        }
+
+        let fs = &mut *self.chain.fs;
+        let offset = fs.cluster_offset(self.cluster)? + (self.index << 5) as u64;
+        let mut raw = [0u8; 32];
+        fs.read_at(offset, &mut raw)?;
+        self.index += 1;
+
+        if raw[0] == 0 {
+            // end marker
+            self.done = true;
+            return Ok(None);
+        }
+        let entry = DirectoryEntry::parse(&raw, offset, &mut self.long_name, self.with_deleted);
+        // the label is a property of the volume, not a file
+        Ok(entry.filter(|entry| self.with_labels || !entry.is_volume_label()))
+    }
+}
+
+impl<B: Read + Seek, M> Iterator for DirectoryIterator<'_, B, M> {
+    type Item = Result<DirectoryEntry, FatError>;
+
+    fn next(&mut self) -> Option<Result<DirectoryEntry, FatError>> {
+        // skip the LFN, deleted and label entries
+        while !self.done {
+            match self.read_entry() {
+                Ok(Some(entry)) => return Some(Ok(entry)),
+                Ok(None) => (),
+                Err(err) => {
+                    self.done = true;
+                    return Some(Err(err));
+                }
+            }
+        }
+        None
+    }
+}
+
+// iterates over the clusters of a chain, stops with
+// an error if the chain goes back to a cluster already
+// seen or to a cluster that can't be part of a chain
+#[derive(Debug)]
+pub struct FatChain<'a, B, M> {
+    fs: &'a mut FAT32<B, M>,
+    // next cluster to return, anything outside of
+    // 2..END_OF_CHAIN ends the iteration
+    current: u32,
+    visited: BTreeSet<u32>,
+}
+
+impl<'a, B: Read + Seek, M> FatChain<'a, B, M> {
+    fn new(fs: &'a mut FAT32<B, M>, start: u32) -> FatChain<'a, B, M> {
+        // empty chain if `start` is 0, as for empty files
+        FatChain {
+            fs,
+            current: start,
+            visited: BTreeSet::new(),
+        }
+    }
+}
+
+impl<B: Read + Seek, M> Iterator for FatChain<'_, B, M> {
+    type Item = Result<u32, FatError>;
+
+    fn next(&mut self) -> Option<Result<u32, FatError>> {
+        let cluster = self.current;
+        if !(2..END_OF_CHAIN).contains(&cluster) {
+            return None;
+        }
+        // nothing more after an error
+        self.current = 0;
+
+        if !self.visited.insert(cluster) {
+            return Some(Err(FatError::CyclicFatChain(cluster)));
+        }
+
+        // the first cluster comes from a directory
+        // entry, and was not checked yet
+        let max = self.fs.total_cluster_count() + 1;
+        if cluster > max {
+            return Some(Err(FatError::InvalidCluster { cluster, max }));
+        }
+        let next = match self.fs.fat_lookup(cluster) {
+            Ok(next) => next,
+            Err(err) => return Some(Err(err)),
+        };
+        if let Err(err) = check_chain_link(cluster, next, max) {
+            return Some(Err(err));
+        }
+
+        self.current = next;
+        Some(Ok(cluster))
+    }
+}
+
+// checks the FAT entry `next` of a cluster of a chain, which
+// must be another cluster up to `max` or an end of chain marker
+pub(crate) fn check_chain_link(cluster: u32, next: u32, max: u32) -> Result<(), FatError> {
+    if next == BAD_CLUSTER {
+        return Err(FatError::BadCluster(cluster));
+    }
+    if next < 2 || (next > max && next < END_OF_CHAIN) {
+        return Err(FatError::InvalidCluster { cluster: next, max });
+    }
+    Ok(())
+}
+
+// writes a new file sequentially, allocating
+// clusters as the data comes
+#[derive(Debug)]
+pub struct FileWriter<'a, B: Read + Write + Seek> {
+    fs: &'a mut FAT32<B, ReadWrite>,
+    // position of the directory entry in the image
+    entry: u64,
+    // last cluster and position in it
+    cluster: u32,
+    offset: u32,
+    size: u32,
+}
+
+impl<B: Read + Write + Seek> Write for FileWriter<'_, B> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let cluster_bytes = self.fs.cluster_size_bytes();
+        if self.size as u64 + buf.len() as u64 > u32::MAX as u64 {
+            return Err(FatError::FileTooLarge.into());
+        }
+
+        // current cluster full, link a new one
+        if self.offset == cluster_bytes {
+            let next = self.fs.allocate_clusters(1)?[0];
+            self.fs.fat_write(self.cluster, next)?;
+            self.cluster = next;
+            self.offset = 0;
+        }
+
+        let len = cmp::min(buf.len(), (cluster_bytes - self.offset) as usize);
+        let seek = self.fs.cluster_offset(self.cluster)? + self.offset as u64;
+        self.fs.write_at(seek, &buf[..len])?;
+        self.offset += len as u32;
+        self.size += len as u32;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        // size of the file, in its directory entry
+        use byteorder::{ByteOrder, LittleEndian};
+
+        let mut raw = [0u8; 4];
+        LittleEndian::write_u32(&mut raw, self.size);
+        self.fs.write_at(self.entry + 28, &raw)?;
+        self.fs.flush()
+    }
+}
+
+impl<B: Read + Write + Seek> Drop for FileWriter<'_, B> {
+    fn drop(&mut self) {
+        // errors can't be reported here, call
+        // flush before to handle them
+        let _ = self.flush();
+    }
+}
+
+// reads a file cluster by cluster, following
+// the FAT chain only when needed
+#[derive(Debug)]
+pub struct FileReader<'a, B, M> {
+    fs: &'a mut FAT32<B, M>,
+    first_cluster: u32,
+    size: u32,
+    // current cluster and byte offset in it;
+    // offset == cluster size means the next read
+    // has to move to the next cluster first
+    cluster: u32,
+    offset: u32,
+    // bytes left until the end of the file
+    remaining: u32,
+    // position in the file, may be past the end
+    pos: u64,
+}
+
+impl<B: Read + Seek, M> FileReader<'_, B, M> {
+    fn cluster_index(&self, pos: u64) -> u64 {
+        // index of the cluster holding byte `pos - 1`,
+        // i.e. the cluster a reader at `pos` is currently in
+        pos.saturating_sub(1) / self.fs.cluster_size_bytes() as u64
+    }
+
+    fn walk(&mut self, steps: u64) -> io::Result<()> {
+        // follow `steps` links of the chain from the current cluster
+        if steps > 0 {
+            let mut chain = self.fs.chain(self.cluster);
+            self.cluster = match chain.nth(steps as usize) {
+                Some(cluster) => cluster?,
+                None => return Err(FatError::UnexpectedEof.into()),
+            };
+        }
+        Ok(())
+    }
+}
+
+impl<B: Read + Seek, M> Read for FileReader<'_, B, M> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let cluster_size = self.fs.cluster_size_bytes();
+        let mut read = 0;
+
+        while read < buf.len() && self.remaining > 0 {
+            // end of current cluster, move to the next one
+            if self.offset == cluster_size {
+                self.walk(1)?;
+                self.offset = 0;
+            }
+            if !(2..END_OF_CHAIN).contains(&self.cluster) {
+                return Err(FatError::UnexpectedEof.into());
+            }
+
+            // read up to the end of the cluster or the file
+            let len = cmp::min(buf.len() - read, (cluster_size - self.offset) as usize);
+            let len = cmp::min(len, self.remaining as usize);
+            let seek = self.fs.cluster_offset(self.cluster)? + self.offset as u64;
+            self.fs.read_at(seek, &mut buf[read..read + len])?;
+
+            read += len;
+            self.offset += len as u32;
+            self.remaining -= len as u32;
+            self.pos += len as u64;
+        }
+
+        Ok(read)
+    }
+}
+
+impl<B: Read + Seek, M> Seek for FileReader<'_, B, M> {
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            io::SeekFrom::Start(n) => Some(n),
+            io::SeekFrom::End(n) => (self.size as u64).checked_add_signed(n),
+            io::SeekFrom::Current(n) => self.pos.checked_add_signed(n),
+        };
+        let target = target
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "invalid seek position"))?;
+
+        if target >= self.size as u64 {
+            // nothing left to read, no need to walk the chain
+            self.remaining = 0;
+            self.pos = target;
+            return Ok(target);
+        }
+
+        // walk forward from the current cluster if possible,
+        // otherwise start over from the first cluster
+        let index = self.cluster_index(target);
+        let current = self.cluster_index(self.pos);
+        if self.pos >= self.size as u64 || index < current {
+            self.cluster = self.first_cluster;
+            self.walk(index)?;
+        } else {
+            self.walk(index - current)?;
+        }
+
+        self.offset = (target - index * self.fs.cluster_size_bytes() as u64) as u32;
+        self.remaining = self.size - target as u32;
+        self.pos = target;
+        Ok(target)
+    }
+}
+
+// names are compared as FAT does, ignoring the case of
+// ascii letters only (8.3 names are stored in uppercase)
+fn fat_name_eq(a: &str, b: &str) -> bool {
+    a.eq_ignore_ascii_case(b)
+}
+
+// matches `name` against a `*` and `?` pattern, ignoring case,
+// backtracking to the last `*` when the rest doesn't match
+fn glob_match(pattern: &[char], name: &str) -> bool {
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // positions after the last `*` and in the name where it matched
+    let mut star = None;
+
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                p += 1;
+                star = Some((p, n));
+            }
+            Some(&c) if c == '?' || c.eq_ignore_ascii_case(&name[n]) => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                // let the last `*` match one more character
+                Some((star_p, star_n)) => {
+                    p = star_p;
+                    n = star_n + 1;
+                    star = Some((star_p, star_n + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+// sectors per cluster and FAT size used when formatting a volume
+// of `sector_count` 512 bytes sectors, with 32 reserved sectors
+// and 2 FATs; the cluster sizes are the ones of the FAT
+// specification, including its "too small for FAT32" row, and
+// the volume must have the 65525 clusters of a FAT32 volume
+fn format_geometry(sector_count: u32) -> Result<(u32, u32), FatError> {
+    let cluster_size = match sector_count {
+        0..=66_600 => return Err(FatError::VolumeTooSmall),
+        66_601..=532_480 => 1,
+        532_481..=16_777_216 => 8,
+        16_777_217..=33_554_432 => 16,
+        33_554_433..=67_108_864 => 32,
+        _ => 64,
+    };
+    // FAT size formula from the FAT specification,
+    // the FATs may be a few sectors larger than needed
+    let data = sector_count - 32;
+    let fat_size = data.div_ceil((256 * cluster_size + 2) / 2);
+    let clusters = (data - 2 * fat_size) / cluster_size;
+    if clusters < 65525 {
+        return Err(FatError::VolumeTooSmall);
+    }
+    Ok((cluster_size, fat_size))
+}
+
+// checks and pads a volume label: up to 11
+// printable ascii characters, spaces if empty
+fn volume_label(label: &str) -> Result<[u8; 11], FatError> {
+    if label.len() > 11 || label.bytes().any(|c| !(b' '..0x7f).contains(&c)) {
+        return Err(FatError::InvalidName);
+    }
+    let mut padded = [b' '; 11];
+    padded[..label.len()].copy_from_slice(label.as_bytes());
+    Ok(padded)
+}
+
+// converts `name` to the padded 11 bytes 8.3 form
+// used in directory entries, e.g. "a.txt" -> "A       TXT"
+fn short_name(name: &str) -> Result<[u8; 11], FatError> {
+    let (base, ext) = match name.rfind('.') {
+        Some(dot) => (&name[..dot], &name[dot + 1..]),
+        None => (name, ""),
+    };
+    let (base, ext) = (base.to_ascii_uppercase(), ext.to_ascii_uppercase());
+    validate_83_name(&base, &ext)?;
+
+    let mut short = [b' '; 11];
+    short[..base.len()].copy_from_slice(base.as_bytes());
+    short[8..8 + ext.len()].copy_from_slice(ext.as_bytes());
+    Ok(short)
+}
+
+#[cfg(feature = "std")]
+// files and directories under `root/relative`, sorted by name
+fn host_tree(
+    root: &path::Path,
+    relative: &path::Path,
+    options: ImportOptions,
+    items: &mut Vec<(path::PathBuf, bool)>,
+    invalid: &mut Vec<String>,
+) -> Result<(), FatError> {
+    // paths are relative to `root`, symbolic links are skipped
+    let mut entries = fs::read_dir(root.join(relative))?.collect::<io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let kind = entry.file_type()?;
+        if !kind.is_dir() && !kind.is_file() {
+            continue;
+        }
+        let path = relative.join(entry.file_name());
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if options.skip_hidden && name.starts_with('.') {
+            continue;
+        }
+        if short_name(&name).is_err() {
+            invalid.push(path.display().to_string());
+            continue;
+        }
+        items.push((path.clone(), kind.is_dir()));
+        if kind.is_dir() {
+            host_tree(root, &path, options, items, invalid)?;
+        }
+    }
+    Ok(())
+}
+
+// checks the two parts of an 8.3 name, without their padding
+pub fn validate_83_name(name: &str, ext: &str) -> Result<(), FatError> {
+    if name.is_empty() || name.len() > 8 || ext.len() > 3 {
+        return Err(FatError::InvalidName);
+    }
+    if !name.bytes().chain(ext.bytes()).all(is_short_name_char) {
+        return Err(FatError::InvalidName);
+    }
+    Ok(())
+}
+
+// closest 8.3 name of `long_name`: uppercase, without the characters
+// that can't be stored, and truncated; None when nothing is left
+pub fn to_83_name(long_name: &str) -> Option<([u8; 8], [u8; 3])> {
+    let long_name = long_name.trim_start_matches('.');
+    let (base, ext) = match long_name.rfind('.') {
+        Some(dot) => (&long_name[..dot], &long_name[dot + 1..]),
+        None => (long_name, ""),
+    };
+    let keep = |part: &str, len: usize| -> Vec<u8> {
+        part.bytes()
+            .map(|c| c.to_ascii_uppercase())
+            .filter(|&c| is_short_name_char(c))
+            .take(len)
+            .collect()
+    };
+    let (base, ext) = (keep(base, 8), keep(ext, 3));
+    if base.is_empty() {
+        return None;
+    }
+
+    let mut name = [b' '; 8];
+    name[..base.len()].copy_from_slice(&base);
+    let mut extension = [b' '; 3];
+    extension[..ext.len()].copy_from_slice(&ext);
+    Some((name, extension))
+}
+
+pub(crate) fn decode_short_name(bytes: &[u8]) -> borrow::Cow<'_, str> {
+    // bytes of another code page than ascii are shown as
+    // replacement characters, only the space padding is
+    // removed (other whitespace is part of the name)
+    match String::from_utf8_lossy(bytes) {
+        borrow::Cow::Borrowed(name) => borrow::Cow::Borrowed(name.trim_end_matches(' ')),
+        borrow::Cow::Owned(name) => borrow::Cow::Owned(name.trim_end_matches(' ').to_string()),
+    }
+}
+
+pub(crate) fn short_full_name(name: &[u8], ext: &[u8]) -> String {
+    // NAME.EXT, without the dot if there is no extension,
+    // shared by the FAT12/16 and FAT32 entries
+    let mut full = String::with_capacity(12);
+    full.push_str(&decode_short_name(name));
+    let ext = decode_short_name(ext);
+    if !ext.is_empty() {
+        full.push('.');
+        full.push_str(&ext);
+    }
+    full
+}
+
+fn is_short_name_char(c: u8) -> bool {
+    // uppercase ascii only, without spaces and reserved characters
+    c > b' ' && c < 0x7f && !c.is_ascii_lowercase() && !b"\"*+,./:;<=>?[\\]|".contains(&c)
+}
+
+// long file name (LFN) entries preceding a regular entry,
+// stored in reverse order, 13 UTF-16 code units each
+#[derive(Debug)]
+pub(crate) struct LongName {
+    parts: Vec<[u16; 13]>,
+    // checksum of the 8.3 name of the regular entry
+    checksum: u8,
+    // sequence number of the next expected entry,
+    // 0 once every part has been read
+    next: u8,
+    // position of the LFN entries in the image
+    offsets: Vec<u64>,
+}
+
+impl LongName {
+    fn push(current: Option<LongName>, raw: &[u8; 32], offset: u64) -> Option<LongName> {
+        use byteorder::{ByteOrder, LittleEndian};
+
+        // deleted LFN entry: drop what was collected
+        if raw[0] == 0xe5 {
+            return None;
+        }
+
+        let seq = raw[0] & 0x1f;
+        let checksum = raw[13];
+        let mut lfn = if raw[0] & 0x40 != 0 {
+            // last part (first in directory order): start over
+            if seq == 0 {
+                return None;
+            }
+            LongName {
+                parts: vec![[0u16; 13]; seq as usize],
+                checksum,
+                next: seq,
+                offsets: Vec::with_capacity(seq as usize),
+            }
+        } else {
+            // any other part must follow the previous one,
+            // there is no part 0 after the first one
+            match current {
+                Some(lfn) if seq != 0 && lfn.next == seq && lfn.checksum == checksum => lfn,
+                _ => return None,
+            }
+        };
+
+        // name characters are split across three fields
+        let part = &mut lfn.parts[seq as usize - 1];
+        let fields = [(1, 0, 5), (14, 5, 6), (28, 11, 2)];
+        for &(offset, start, len) in fields.iter() {
+            for i in 0..len {
+                part[start + i] = LittleEndian::read_u16(&raw[offset + 2 * i..]);
+            }
+        }
+        lfn.next = seq - 1;
+        lfn.offsets.push(offset);
+
+        Some(lfn)
+    }
+
+    fn finish(self, name: &[u8; 11]) -> Option<(String, Vec<u64>)> {
+        // checksum of the 8.3 name, ignored if it does not
+        // match (e.g. the entry was renamed by an old driver)
+        let checksum = name
+            .iter()
+            .fold(0u8, |sum, &c| sum.rotate_right(1).wrapping_add(c));
+        if self.next != 0 || checksum != self.checksum {
+            return None;
+        }
+
+        // the name ends at the first null code unit,
+        // the rest of the last part is padding
+        let units: Vec<u16> = self
+            .parts
+            .iter()
+            .flat_map(|part| part.iter().cloned())
+            .take_while(|&c| c != 0)
+            .collect();
+
+        let name = String::from_utf16(&units).ok()?;
+        Some((name, self.offsets))
+    }
+}
+
+// problems found by check_integrity, with the
+// path of the entry using the cluster ("/" for root)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum IntegrityError {
+    // a chain goes to a cluster outside of the data region
+    InvalidCluster {
+        path: String,
+        cluster: u32,
+    },
+    // a chain goes through a cluster marked as bad
+    BadCluster {
+        path: String,
+        cluster: u32,
+    },
+    // a chain loops back on itself at this cluster
+    CyclicChain {
+        path: String,
+        cluster: u32,
+    },
+    // a cluster used by two entries
+    CrossLinked {
+        path: String,
+        other: String,
+        cluster: u32,
+    },
+    // file size doesn't match the length of its chain
+    SizeMismatch {
+        path: String,
+        size: u32,
+        clusters: u32,
+    },
+    // allocated cluster not used by any entry
+    OrphanedCluster(u32),
+}
+
+// free clusters information from the FSInfo sector,
+// 0xffffffff when unknown
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FsInfo {
+    pub free_clusters: u32,
+    // hint: where to start looking for a free cluster
+    pub next_free: u32,
+}
+
+// fields of the boot record, with their names
+// from the FAT specification
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BootSector {
+    pub oem_name: String,
+    pub bytes_per_sector: u16,
+    pub sectors_per_cluster: u8,
+    pub reserved_sectors: u16,
+    pub num_fats: u8,
+    // 0 on FAT32, as well as the 16 bits sizes
+    pub root_entries: u16,
+    pub total_sectors_16: u16,
+    pub media: u8,
+    pub fat_size_16: u16,
+    pub sectors_per_track: u16,
+    pub num_heads: u16,
+    pub hidden_sectors: u32,
+    pub total_sectors: u32,
+    pub fat_size: u32,
+    pub ext_flags: u16,
+    pub version: u16,
+    pub root_cluster: u32,
+    pub fs_info_sector: u16,
+    pub backup_boot_sector: u16,
+    pub drive_number: u8,
+    pub boot_signature: u8,
+    pub volume_serial: u32,
+    pub volume_label: [u8; 11],
+    pub fs_type_label: [u8; 8],
+}
+
+impl BootSector {
+    pub(crate) fn parse(data: &[u8; 90]) -> Result<BootSector, FatError> {
+        use byteorder::{ByteOrder, LittleEndian};
+
+        // skip boot jump, the OEM identifier is
+        // only informative and may not be valid text
+        let oem_name = String::from_utf8_lossy(&data[3..11]).trim_end().to_string();
+        let bytes_per_sector = LittleEndian::read_u16(&data[11..]);
+        // 512, 1024, 2048 or 4096 bytes
+        if !(512..=4096).contains(&bytes_per_sector) || !bytes_per_sector.is_power_of_two() {
+            return Err(FatError::InvalidBootRecord("unsupported sector size"));
+        }
+        // every division by the cluster size relies on this
+        let sectors_per_cluster = data[13];
+        if !sectors_per_cluster.is_power_of_two() {
+            return Err(FatError::InvalidBootRecord(
+                "sectors per cluster not a power of two",
+            ));
+        }
+        let num_fats = data[16];
+        if num_fats == 0 {
+            return Err(FatError::InvalidBootRecord("no FAT on the volume"));
+        }
+        // sector numbers are stored on 32 bits, up to the
+        // end of the FATs at least
+        let reserved_sectors = LittleEndian::read_u16(&data[14..]);
+        let fat_size = LittleEndian::read_u32(&data[36..]);
+        (num_fats as u32)
+            .checked_mul(fat_size)
+            .and_then(|size| size.checked_add(reserved_sectors as u32))
+            .ok_or(FatError::InvalidBootRecord("FATs too large"))?;
+
+        // extended FAT32 boot record: bit 7 of the flags
+        // disables FAT mirroring, bits 0-3 then give the
+        // only active FAT
+        let ext_flags = LittleEndian::read_u16(&data[40..]);
+        let flags = ExtendedFlags::from(ext_flags);
+        if flags.mirroring_disabled && flags.active_fat >= num_fats {
+            return Err(FatError::InvalidBootRecord("active FAT out of range"));
+        }
+
+        // extended boot signature, 0x29 means
+        // serial number, label and type are present
+        let boot_signature = data[66];
+        if boot_signature != 0x28 && boot_signature != 0x29 {
+            return Err(FatError::InvalidSignature(boot_signature));
+        }
+        // label: 11 ascii bytes padded with spaces,
+        // checked once here so volume_name can't fail
+        let mut volume_label = [0u8; 11];
+        volume_label.copy_from_slice(&data[71..82]);
+        str::from_utf8(&volume_label)?;
+        let mut fs_type_label = [0u8; 8];
+        fs_type_label.copy_from_slice(&data[82..90]);
+
+        Ok(BootSector {
+            oem_name,
+            bytes_per_sector,
+            sectors_per_cluster,
+            reserved_sectors,
+            num_fats,
+            root_entries: LittleEndian::read_u16(&data[17..]),
+            total_sectors_16: LittleEndian::read_u16(&data[19..]),
+            media: data[21],
+            fat_size_16: LittleEndian::read_u16(&data[22..]),
+            sectors_per_track: LittleEndian::read_u16(&data[24..]),
+            num_heads: LittleEndian::read_u16(&data[26..]),
+            hidden_sectors: LittleEndian::read_u32(&data[28..]),
+            total_sectors: LittleEndian::read_u32(&data[32..]),
+            fat_size,
+            ext_flags,
+            version: LittleEndian::read_u16(&data[42..]),
+            root_cluster: LittleEndian::read_u32(&data[44..]),
+            fs_info_sector: LittleEndian::read_u16(&data[48..]),
+            backup_boot_sector: LittleEndian::read_u16(&data[50..]),
+            drive_number: data[64],
+            boot_signature,
+            volume_serial: LittleEndian::read_u32(&data[67..]),
+            volume_label,
+            fs_type_label,
+        })
+    }
+}
+
+// FAT mirroring flags of the boot record, `active_fat`
+// is only meaningful when mirroring is disabled
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ExtendedFlags {
+    pub mirroring_disabled: bool,
+    pub active_fat: u8,
+}
+
+impl From<u16> for ExtendedFlags {
+    fn from(flags: u16) -> ExtendedFlags {
+        // bits 0-3: active FAT, bit 7: mirroring disabled
+        ExtendedFlags {
+            mirroring_disabled: flags & 0x80 != 0,
+            active_fat: (flags & 0xf) as u8,
+        }
+    }
+}
+
+// result of defragment_file, `moved` is false
+// when the file was already contiguous
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DefragReport {
+    pub moved: bool,
+    pub old_first: u32,
+    pub new_first: u32,
+    pub clusters_moved: u32,
+}
+
+// what repair changed, `changes_written` counts
+// every FAT entry and directory entry written
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RepairReport {
+    pub orphans_freed: u32,
+    pub cross_links_broken: u32,
+    pub invalid_links_cut: u32,
+    pub sizes_corrected: u32,
+    pub changes_written: u32,
+}
+
+// how secure_delete overwrites a file, by default
+// with a single pass of zeros
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SecureDeleteOptions {
+    pub passes: u8,
+    pub pattern: u8,
+}
+
+impl Default for SecureDeleteOptions {
+    fn default() -> SecureDeleteOptions {
+        SecureDeleteOptions {
+            passes: 1,
+            pattern: 0,
+        }
+    }
+}
+
+// number of FAT entries of each kind, `total`
+// includes the 2 reserved entries
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ClusterStats {
+    pub total: u32,
+    pub free: u32,
+    pub bad: u32,
+    pub reserved: u32,
+    pub used: u32,
+}
+
+// free clusters of the volume, one bit per
+// cluster of the data region, from cluster 2
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FreeSpaceMap {
+    bits: Vec<u64>,
+    clusters: u32,
+}
+
+impl FreeSpaceMap {
+    fn new(clusters: u32) -> FreeSpaceMap {
+        FreeSpaceMap {
+            bits: vec![0; (clusters as usize).div_ceil(64)],
+            clusters,
+        }
+    }
+
+    fn set_free(&mut self, cluster: u32) {
+        let index = (cluster - 2) as usize;
+        self.bits[index / 64] |= 1 << (index % 64);
+    }
+
+    pub fn is_free(&self, cluster: u32) -> bool {
+        // clusters outside of the data region are never free
+        if cluster < 2 || cluster - 2 >= self.clusters {
+            return false;
+        }
+        let index = (cluster - 2) as usize;
+        self.bits[index / 64] & (1 << (index % 64)) != 0
+    }
+
+    pub fn free_count(&self) -> u32 {
+        self.bits.iter().map(|word| word.count_ones()).sum()
+    }
+
+    pub fn first_free(&self) -> Option<u32> {
+        // skips 64 used clusters at a time
+        let (index, word) = self.bits.iter().enumerate().find(|(_, &word)| word != 0)?;
+        Some(index as u32 * 64 + word.trailing_zeros() + 2)
+    }
+
+    pub fn find_contiguous(&self, n: u32) -> Option<u32> {
+        // first cluster of the first run of `n` free clusters
+        let mut run = 0;
+        for cluster in 2..self.clusters + 2 {
+            run = if self.is_free(cluster) { run + 1 } else { 0 };
+            if run == n && n > 0 {
+                return Some(cluster + 1 - n);
+            }
+        }
+        None
+    }
+}
+
+// metadata of a file, as returned by stat
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FileStat {
+    pub name: String,
+    pub long_name: Option<String>,
+    pub size: u32,
+    pub attributes: FileAttributes,
+    pub created: Timestamp,
+    pub modified: Timestamp,
+    // date only, time is midnight
+    pub accessed: Timestamp,
+    pub first_cluster: u32,
+}
+
+impl From<&DirectoryEntry> for FileStat {
+    fn from(entry: &DirectoryEntry) -> FileStat {
+        FileStat {
+            name: entry.full_name(),
+            long_name: entry.long_name().map(String::from),
+            size: entry.size,
+            attributes: entry.attributes(),
+            created: entry.created(),
+            modified: entry.modified(),
+            accessed: entry.accessed(),
+            first_cluster: entry.cluster,
+        }
+    }
+}
+
+// describes one entry in
+// a directory listing
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DirectoryEntry {
+    name: [u8; 11],
+    long_name: Option<String>,
+    flags: u8,
+    // NTRes byte, kept as is
+    reserved: u8,
+    cluster: u32,
+    size: u32,
+    times: EntryTimes,
+    // position of the entry and of its LFN entries in the image,
+    // only meaningful for the handle that read them
+    #[cfg_attr(feature = "serde", serde(skip))]
+    offset: u64,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    long_name_offsets: Vec<u64>,
+    // 0xe5 entry, from read_directory_with_deleted
+    deleted: bool,
+}
+
+// dates and times fields of a directory entry, in FAT format
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct EntryTimes {
+    created_tenths: u8,
+    created_time: u16,
+    created_date: u16,
+    accessed_date: u16,
+    modified_time: u16,
+    modified_date: u16,
+}
+
+// order of the entries returned by read_directory_sorted,
+// names are compared ignoring case
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SortKey {
+    Name,
+    Size,
+    SizeDesc,
+    Modified,
+    Created,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EntryType {
+    File(File),
+    Dir(Directory),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct File {
+    pub(crate) cluster: u32,
+    pub(crate) size: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Directory {
+    pub(crate) cluster: u32,
+}
+
+impl DirectoryEntry {
+    pub(crate) fn parse(
+        raw: &[u8; 32],
+        offset: u64,
+        long_name: &mut Option<LongName>,
+        with_deleted: bool,
+    ) -> Option<DirectoryEntry> {
+        use byteorder::{ByteOrder, LittleEndian};
+
+        let flags = raw[11];
+
+        // flag 0xf = special entry for long filenames,
+        // its content belongs to the next regular entry
+        if flags == 0xf {
+            *long_name = LongName::push(long_name.take(), raw, offset);
+            return None;
+        }
+
+        let deleted = raw[0] == 0xe5;
+        if deleted {
+            // deleted entry, its LFN entries are deleted as well
+            *long_name = None;
+            if !with_deleted {
+                return None;
+            }
+        }
+
+        let mut name = [0u8; 11];
+        name.copy_from_slice(&raw[..11]);
+        if deleted {
+            // the first character is lost
+            name[0] = b'?';
+        }
+        // first cluster is split in two 16 bits words
+        let hi = LittleEndian::read_u16(&raw[20..]) as u32;
+        let lo = LittleEndian::read_u16(&raw[26..]) as u32;
+        let cluster = (hi << 16) | lo;
+        let size = LittleEndian::read_u32(&raw[28..]);
+        let times = EntryTimes {
+            created_tenths: raw[13],
+            created_time: LittleEndian::read_u16(&raw[14..]),
+            created_date: LittleEndian::read_u16(&raw[16..]),
+            accessed_date: LittleEndian::read_u16(&raw[18..]),
+            modified_time: LittleEndian::read_u16(&raw[22..]),
+            modified_date: LittleEndian::read_u16(&raw[24..]),
+        };
+
+        // the LFN checksum is computed on the name as stored
+        let (long_name, long_name_offsets) =
+            long_name.take().and_then(|lfn| lfn.finish(&name)).unzip();
+        if name[0] == 0x05 {
+            // stands for a name starting with 0xe5 (valid in
+            // Kanji), which would read as a deleted entry
+            name[0] = 0xe5;
+        }
+        Some(DirectoryEntry {
+            name,
+            long_name,
+            flags,
+            reserved: raw[12],
+            cluster,
+            size,
+            times,
+            offset,
+            long_name_offsets: long_name_offsets.unwrap_or_default(),
+            deleted,
+        })
+    }
+
+    pub fn from_bytes(raw: [u8; 32]) -> Option<DirectoryEntry> {
+        // a single entry, without its long name nor position,
+        // None for the end marker and LFN entries
+        if raw[0] == 0 {
+            return None;
+        }
+        DirectoryEntry::parse(&raw, 0, &mut None, true)
+    }
+
+    pub fn raw_bytes(&self) -> [u8; 32] {
+        use byteorder::{ByteOrder, LittleEndian};
+
+        // the entry as stored in a directory
+        let mut raw = [0u8; 32];
+        raw[..11].copy_from_slice(&self.name);
+        if self.deleted {
+            raw[0] = 0xe5;
+        } else if raw[0] == 0xe5 {
+            raw[0] = 0x05;
+        }
+        raw[11] = self.flags;
+        raw[12] = self.reserved;
+        raw[13] = self.times.created_tenths;
+        LittleEndian::write_u16(&mut raw[14..], self.times.created_time);
+        LittleEndian::write_u16(&mut raw[16..], self.times.created_date);
+        LittleEndian::write_u16(&mut raw[18..], self.times.accessed_date);
+        LittleEndian::write_u16(&mut raw[20..], (self.cluster >> 16) as u16);
+        LittleEndian::write_u16(&mut raw[22..], self.times.modified_time);
+        LittleEndian::write_u16(&mut raw[24..], self.times.modified_date);
+        LittleEndian::write_u16(&mut raw[26..], self.cluster as u16);
+        LittleEndian::write_u32(&mut raw[28..], self.size);
+        raw
+    }
+
+    pub fn name(&self) -> borrow::Cow<'_, str> {
+        // removes the padding spaces around the name
+        decode_short_name(&self.name[..8])
+    }
+
+    pub fn extension(&self) -> borrow::Cow<'_, str> {
+        // removes the padding spaces around the extension
+        decode_short_name(&self.name[8..])
+    }
+
+    pub fn long_name(&self) -> Option<&str> {
+        // name from the LFN entries, if any
+        self.long_name.as_deref()
+    }
+
+    pub fn created(&self) -> Timestamp {
+        // creation time has a 10 ms resolution
+        let times = &self.times;
+        Timestamp::from_fat_date_time(times.created_date, times.created_time, times.created_tenths)
+    }
+
+    pub fn modified(&self) -> Timestamp {
+        // last write time, with a 2 s resolution
+        Timestamp::from_fat_date_time(self.times.modified_date, self.times.modified_time, 0)
+    }
+
+    pub fn accessed(&self) -> Timestamp {
+        // last access: date only, time is midnight
+        Timestamp::from_fat_date_time(self.times.accessed_date, 0, 0)
+    }
+
+    fn has_name(&self, name: &str) -> bool {
+        fat_name_eq(&self.full_name(), name)
+            || self
+                .long_name()
+                .is_some_and(|long_name| fat_name_eq(long_name, name))
+    }
+
+    pub fn full_name(&self) -> String {
+        // returns the full name of the file : NAME.EXT
+        short_full_name(&self.name[..8], &self.name[8..])
+    }
+
+    pub fn attributes(&self) -> FileAttributes {
+        // unknown bits are kept as is
+        FileAttributes::from_bits_retain(self.flags)
+    }
+
+    pub fn set_attributes(&mut self, attr: FileAttributes) {
+        // only changes this entry, not the image
+        self.flags = attr.bits();
+    }
+
+    pub fn size(&self) -> u32 {
+        // in bytes, 0 for directories
+        self.size
+    }
+
+    pub fn is_deleted(&self) -> bool {
+        self.deleted
+    }
+
+    pub fn is_directory(&self) -> bool {
+        self.attributes().contains(FileAttributes::DIRECTORY)
+    }
+
+    pub fn is_dot_entry(&self) -> bool {
+        // `.` and `..`, at the start of every directory but the root
+        &self.name == b".          " || &self.name == b"..         "
+    }
+
+    pub fn is_volume_label(&self) -> bool {
+        // with the directory bit, the entry is not a label
+        let attributes = self.attributes();
+        attributes.contains(FileAttributes::VOLUME_LABEL)
+            && !attributes.contains(FileAttributes::DIRECTORY)
+    }
+
+    pub fn entry_type(&self) -> EntryType {
+        EntryType::from(self)
+    }
+}
+
+impl From<&DirectoryEntry> for EntryType {
+    fn from(entry: &DirectoryEntry) -> EntryType {
+        if entry.is_directory() {
+            EntryType::Dir(Directory {
+                cluster: entry.cluster,
+            })
+        } else {
+            EntryType::File(File {
+                cluster: entry.cluster,
+                size: entry.size,
+            })
+        }
+    }
+}
+
+impl From<DirectoryEntry> for EntryType {
+    fn from(entry: DirectoryEntry) -> EntryType {
+        EntryType::from(&entry)
+    }
+}
+
+impl fmt::Display for DirectoryEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // ls -l like line: permissions, size, date and name,
+        // read-only entries are not writable
+        let attributes = self.attributes();
+        let write = if attributes.contains(FileAttributes::READ_ONLY) {
+            '-'
+        } else {
+            'w'
+        };
+        let (kind, exec) = if self.is_directory() {
+            ('d', 'x')
+        } else {
+            ('-', '-')
+        };
+        write!(f, "{}r{}{}r-{}r-{} ", kind, write, exec, exec, exec)?;
+
+        if self.is_directory() {
+            write!(f, "{:>10}", "<DIR>")?;
+        } else {
+            write!(f, "{:>10}", self.size)?;
+        }
+
+        let modified = self.modified();
+        write!(
+            f,
+            "  {:04}-{:02}-{:02}  {}",
+            modified.year,
+            modified.month,
+            modified.day,
+            self.long_name()
+                .map_or_else(|| self.full_name(), String::from)
+        )
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use byteorder::{ByteOrder, LittleEndian};
+
+    // sector size of the test images
+    const SECTOR_SIZE: u32 = 0x200;
+
+    // builds small FAT32 images: 512 bytes sectors by default,
+    // 1 sector per cluster, root directory at cluster 2
+    struct TestImage {
+        data: Vec<u8>,
+        sector_size: u32,
+        fat_count: u32,
+        fat_size: u32,
+    }
+
+    impl TestImage {
+        fn new(sector_count: u32, fat_count: u32) -> TestImage {
+            TestImage::with_sector_size(sector_count, fat_count, SECTOR_SIZE)
+        }
+
+        fn with_sector_size(sector_count: u32, fat_count: u32, sector_size: u32) -> TestImage {
+            let fat_size = sector_count * 4 / sector_size + 1;
+            let mut data = vec![0u8; (sector_count * sector_size) as usize];
+
+            LittleEndian::write_u16(&mut data[11..], sector_size as u16);
+            data[13] = 1;
+            LittleEndian::write_u16(&mut data[14..], 32);
+            data[16] = fat_count as u8;
+            LittleEndian::write_u32(&mut data[32..], sector_count);
+            LittleEndian::write_u32(&mut data[36..], fat_size);
+            LittleEndian::write_u32(&mut data[44..], 2);
+            data[3..11].copy_from_slice(b"FAT-RS  ");
+            data[66] = 0x29;
+            LittleEndian::write_u32(&mut data[67..], 0x1234abcd);
+            data[71..82].copy_from_slice(b"TEST       ");
+
+            let mut image = TestImage {
+                data,
+                sector_size,
+                fat_count,
+                fat_size,
+            };
+            image.chain(&[2]);
+            image
+        }
+
+        fn fs_info(&mut self, free: u32) -> &mut TestImage {
+            // FSInfo in sector 1
+            LittleEndian::write_u16(&mut self.data[48..], 1);
+            let sector = &mut self.data[self.sector_size as usize..];
+            LittleEndian::write_u32(&mut sector[0..], 0x41615252);
+            LittleEndian::write_u32(&mut sector[484..], 0x61417272);
+            LittleEndian::write_u32(&mut sector[488..], free);
+            LittleEndian::write_u32(&mut sector[492..], 0xffffffff);
+            self
+        }
+
+        fn ext_flags(&mut self, flags: u16) -> &mut TestImage {
+            LittleEndian::write_u16(&mut self.data[40..], flags);
+            self
+        }
+
+        fn fat_entry(&mut self, fat: u32, cluster: u32, value: u32) -> &mut TestImage {
+            let offset = (32 + fat * self.fat_size) * self.sector_size + cluster * 4;
+            LittleEndian::write_u32(&mut self.data[offset as usize..], value);
+            self
+        }
+
+        fn chain(&mut self, clusters: &[u32]) -> &mut TestImage {
+            // link the clusters in every FAT
+            for fat in 0..self.fat_count {
+                for pair in clusters.windows(2) {
+                    self.fat_entry(fat, pair[0], pair[1]);
+                }
+                self.fat_entry(fat, clusters[clusters.len() - 1], 0x0fffffff);
+            }
+            self
+        }
+
+        fn cluster_offset(&self, cluster: u32) -> usize {
+            ((32 + self.fat_count * self.fat_size + cluster - 2) * self.sector_size) as usize
+        }
+
+        fn cluster(&mut self, cluster: u32, content: &[u8]) -> &mut TestImage {
+            let offset = self.cluster_offset(cluster);
+            self.data[offset..offset + content.len()].copy_from_slice(content);
+            self
+        }
+
+        fn entry(
+            &mut self,
+            index: usize,
+            name: &[u8; 11],
+            flags: u8,
+            cluster: u32,
+            size: u32,
+        ) -> &mut TestImage {
+            // add an entry to the root directory
+            let offset = self.cluster_offset(2) + index * 32;
+            let raw = &mut self.data[offset..offset + 32];
+            raw[..11].copy_from_slice(name);
+            raw[11] = flags;
+            LittleEndian::write_u16(&mut raw[20..], (cluster >> 16) as u16);
+            LittleEndian::write_u16(&mut raw[26..], cluster as u16);
+            LittleEndian::write_u32(&mut raw[28..], size);
+            self
+        }
+
+        fn path(&self, name: &str) -> std::path::PathBuf {
+            let path = std::env::temp_dir().join(format!("fat-rs-{}.img", name));
+            fs::write(&path, &self.data).unwrap();
+            path
+        }
+
+        fn open(&self, name: &str) -> FAT32 {
+            FAT32::new(&self.path(name)).unwrap()
+        }
+    }
+
+    fn file<B: Read + Seek, M>(fs: &mut FAT32<B, M>, name: &str) -> File {
+        let root = fs.root_directory();
+        let entry = fs
+            .read_directory(root)
+            .unwrap()
+            .into_iter()
+            .find(|entry| entry.full_name() == name)
+            .unwrap();
+        match entry.entry_type() {
+            EntryType::File(file) => file,
+            EntryType::Dir(_) => panic!("{} is a directory", name),
+        }
+    }
+
+    fn content(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[test]
+    fn read_mirrored_fats() {
+        let data = content(1300);
+        let mut fs = TestImage::new(0x1000, 2)
+            .entry(0, b"DATA    BIN", 0x20, 3, 1300)
+            .chain(&[3, 5, 4])
+            .cluster(3, &data[..512])
+            .cluster(5, &data[512..1024])
+            .cluster(4, &data[1024..])
+            .open("mirrored");
+
+        let file = file(&mut fs, "DATA.BIN");
+        assert_eq!(fs.read_file(file).unwrap(), data);
+        assert_eq!(fs.chain_to_vec(3).unwrap(), [3, 5, 4]);
+        assert_eq!(fs.cluster_chain_length(3).unwrap(), 3);
+        assert_eq!(fs.cluster_chain_length(0).unwrap(), 0);
+        assert_eq!(fs.volume_serial_number(), 0x1234abcd);
+        assert_eq!(fs.oem_name(), "FAT-RS");
+    }
+
+    #[test]
+    fn read_active_fat() {
+        // mirroring disabled, only the second FAT is valid
+        let data = content(700);
+        let mut fs = TestImage::new(0x1000, 2)
+            .ext_flags(0x81)
+            .entry(0, b"DATA    BIN", 0x20, 3, 700)
+            .chain(&[3, 4])
+            .fat_entry(0, 3, 0x0fffffff)
+            .cluster(3, &data[..512])
+            .cluster(4, &data[512..])
+            .open("active");
+        let flags = fs.extended_flags();
+        assert!(flags.mirroring_disabled);
+        assert_eq!(flags.active_fat, 1);
+
+        let file = file(&mut fs, "DATA.BIN");
+        assert_eq!(fs.read_file(file).unwrap(), data);
+    }
+
+    #[test]
+    fn large_sectors() {
+        // 4096 bytes sectors (and clusters)
+        let data = content(5000);
+        let path = TestImage::with_sector_size(0x400, 1, 4096)
+            .entry(0, b"DATA    BIN", 0x20, 3, 5000)
+            .chain(&[3, 4])
+            .cluster(3, &data[..4096])
+            .cluster(4, &data[4096..])
+            .path("sectors");
+        let mut fs = FAT32::open_rw(&path).unwrap();
+        assert_eq!(fs.volume_size(), 0x400 * 4096);
+        assert_eq!(fs.cluster_size_bytes(), 4096);
+
+        let file = file(&mut fs, "DATA.BIN");
+        assert_eq!(fs.read_file(file).unwrap(), data);
+
+        let root = fs.root_directory();
+        let file = fs.write_file(root, "MORE.BIN", &data).unwrap();
+        assert_eq!(fs.read_file(file).unwrap(), data);
+    }
+
+    #[test]
+    fn validate_boot_sectors() {
+        let mut image = Vec::new();
+        FAT32::format(io::Cursor::new(&mut image), "BOOT", 64 << 20).unwrap();
+        let check = |offset: usize, value: &[u8]| {
+            // only the boot sector is read
+            let mut image = image[..512].to_vec();
+            image[offset..offset + value.len()].copy_from_slice(value);
+            // fields FAT32 can't work with are refused on opening
+            let result = FAT32::from_bytes(&image).and_then(|mut fs| fs.validate_boot_sector());
+            match result {
+                Err(FatError::InvalidBootRecord(reason)) => reason,
+                result => panic!("{:?}", result),
+            }
+        };
+
+        let mut fs = FAT32::from_bytes(&image).unwrap();
+        fs.validate_boot_sector().unwrap();
+        assert!(check(510, &[0, 0]).contains("0x55aa"));
+        assert!(check(13, &[3]).contains("per cluster"));
+        assert!(check(13, &[0]).contains("per cluster"));
+        assert!(check(16, &[3]).contains("FATs"));
+        assert!(check(17, &[0, 2]).contains("root entry"));
+        assert!(check(19, &[0, 1]).contains("sector count"));
+        assert!(check(22, &[1, 0]).contains("FAT size"));
+    }
+
+    #[test]
+    fn unsupported_sector_size() {
+        let mut image = TestImage::new(0x1000, 1);
+        LittleEndian::write_u16(&mut image.data[11..], 768);
+        assert!(matches!(
+            FAT32::new(&image.path("sector-size")),
+            Err(FatError::InvalidBootRecord(_))
+        ));
+    }
+
+    #[test]
+    fn in_memory_images() {
+        let data = content(700);
+        let mut image = TestImage::new(0x1000, 1);
+        image
+            .entry(0, b"DATA    BIN", 0x20, 3, 700)
+            .chain(&[3, 4])
+            .cluster(3, &data[..512])
+            .cluster(4, &data[512..]);
+
+        // read only backend
+        assert!(matches!(FAT32::from_bytes(&[]), Err(FatError::Io(_))));
+        let mut fs = FAT32::from_bytes(&image.data).unwrap();
+        assert_eq!(fs.volume_name(), "TEST");
+        let file = file(&mut fs, "DATA.BIN");
+        assert_eq!(fs.read_file(file).unwrap(), data);
+
+        // writable backend
+        let mut fs = FAT32::from_reader_rw(io::Cursor::new(image.data.clone())).unwrap();
+        let root = fs.root_directory();
+        let file = fs.write_file(root, "MORE.BIN", &data).unwrap();
+        assert_eq!(fs.read_file(file).unwrap(), data);
+    }
+
+    #[test]
+    fn read_high_cluster() {
+        // clusters above 0xffff need the high word of the entry
+        let data = content(600);
+        let mut fs = TestImage::new(72000, 1)
+            .entry(0, b"HIGH    BIN", 0x20, 70000, 600)
+            .chain(&[70000, 70001])
+            .cluster(70000, &data[..512])
+            .cluster(70001, &data[512..])
+            .open("high");
+
+        let file = file(&mut fs, "HIGH.BIN");
+        assert_eq!(fs.read_file(file).unwrap(), data);
+    }
+
+    #[test]
+    fn write_and_read_back() {
+        let sizes = [0, 1, 511, 512, 513, 2000];
+        let path = TestImage::new(0x1000, 2).fs_info(1000).path("write");
+        let mut fs = FAT32::open_rw(&path).unwrap();
+
+        // more files than a root cluster can hold
+        for i in 0..20 {
+            let root = fs.root_directory();
+            let data = content(sizes[i % sizes.len()]);
+            fs.write_file(root, &format!("file{}.bin", i), &data)
+                .unwrap();
+        }
+        let root = fs.root_directory();
+        assert!(matches!(
+            fs.write_file(root, "FILE3.BIN", b""),
+            Err(FatError::AlreadyExists)
+        ));
+        let root = fs.root_directory();
+        assert!(matches!(
+            fs.write_file(root, "too long.bin", b""),
+            Err(FatError::InvalidName)
+        ));
+
+        for i in 0..20 {
+            let file = file(&mut fs, &format!("FILE{}.BIN", i));
+            let data = fs.read_file(file).unwrap();
+            assert_eq!(data, content(sizes[i % sizes.len()]));
+        }
+
+        // 9 data clusters per 6 files, 1 more directory cluster
+        fs.flush().unwrap();
+        let image = fs::read(&path).unwrap();
+        let free = LittleEndian::read_u32(&image[SECTOR_SIZE as usize + 488..]);
+        assert_eq!(free, 1000 - 3 * 9 - 1 - 1);
+
+        // both FATs were updated
+        let fat_size = (0x1000 * 4 / SECTOR_SIZE + 1) as usize * SECTOR_SIZE as usize;
+        let fat = 32 * SECTOR_SIZE as usize;
+        assert_eq!(
+            image[fat..fat + fat_size],
+            image[fat + fat_size..fat + 2 * fat_size]
+        );
+    }
+
+    #[test]
+    fn create_directories() {
+        let mut fs = FAT32::open_rw(&TestImage::new(0x1000, 1).path("mkdir")).unwrap();
+
+        let root = fs.root_directory();
+        let docs = fs.create_directory(root, "docs").unwrap();
+        let sub = fs.create_directory(docs, "sub").unwrap();
+        fs.write_file(sub, "a.txt", b"hello").unwrap();
+        let root = fs.root_directory();
+        assert!(matches!(
+            fs.create_directory(root, "directory"),
+            Err(FatError::InvalidName)
+        ));
+
+        let docs = match fs.read_directory(fs.root_directory()).unwrap()[0].entry_type() {
+            EntryType::Dir(dir) => dir,
+            EntryType::File(_) => panic!("DOCS is a file"),
+        };
+        let docs_cluster = docs.cluster;
+        let entries = fs.read_directory(docs).unwrap();
+        let names: Vec<String> = entries.iter().map(|entry| entry.full_name()).collect();
+        assert_eq!(names, [".", "..", "SUB"]);
+        // .. of a directory in root points to cluster 0
+        assert_eq!(entries[0].cluster, docs_cluster);
+        assert_eq!(entries[1].cluster, 0);
+
+        let sub = match entries[2].entry_type() {
+            EntryType::Dir(dir) => dir,
+            EntryType::File(_) => panic!("SUB is a file"),
+        };
+        let entries = fs.read_directory(sub).unwrap();
+        assert_eq!(entries[1].cluster, docs_cluster);
+        match entries[2].entry_type() {
+            EntryType::File(file) => assert_eq!(fs.read_file(file).unwrap(), b"hello"),
+            EntryType::Dir(_) => panic!("A.TXT is a directory"),
+        }
+    }
+
+    #[test]
+    fn delete_files() {
+        let path = TestImage::new(0x1000, 1).fs_info(1000).path("delete");
+        let mut fs = FAT32::open_rw(&path).unwrap();
+
+        let root = fs.root_directory();
+        fs.write_file(root, "a.bin", &content(1500)).unwrap();
+        let root = fs.root_directory();
+        fs.write_file(root, "empty", b"").unwrap();
+        let root = fs.root_directory();
+        let a = fs.read_directory(root).unwrap().remove(0);
+
+        let root = fs.root_directory();
+        fs.delete_file(root, "A.BIN").unwrap();
+        let root = fs.root_directory();
+        fs.delete_file(root, "empty").unwrap();
+        let root = fs.root_directory();
+        assert!(matches!(
+            fs.delete_file(root, "a.bin"),
+            Err(FatError::NotFound)
+        ));
+        assert!(fs.read_directory(fs.root_directory()).unwrap().is_empty());
+
+        // clusters are free again, and reused first
+        for cluster in a.cluster..a.cluster + 3 {
+            assert_eq!(fs.fat_lookup(cluster).unwrap(), 0);
+        }
+        fs.flush().unwrap();
+        let image = fs::read(&path).unwrap();
+        assert_eq!(
+            LittleEndian::read_u32(&image[SECTOR_SIZE as usize + 488..]),
+            1000
+        );
+        let root = fs.root_directory();
+        let b = fs.write_file(root, "b.bin", b"b").unwrap();
+        assert_eq!(b.cluster, a.cluster);
+    }
+
+    #[test]
+    fn delete_corrupt_chains() {
+        let path = TestImage::new(0x1000, 1)
+            .entry(0, b"LOOP    BIN", 0x20, 3, 1000)
+            .chain(&[3, 4])
+            .fat_entry(0, 4, 3)
+            .entry(1, b"OUT     BIN", 0x20, 5, 1000)
+            .chain(&[5, 6])
+            .fat_entry(0, 6, 0x0ffffff0)
+            .path("delete-corrupt");
+        let mut fs = FAT32::open_rw(&path).unwrap();
+        let root = fs.root_directory();
+        assert!(matches!(
+            fs.delete_file(root, "LOOP.BIN"),
+            Err(FatError::CyclicFatChain(3))
+        ));
+        assert!(matches!(
+            fs.delete_file(root, "OUT.BIN"),
+            Err(FatError::InvalidCluster { .. })
+        ));
+
+        // still owned by their entries, left for repair
+        fs.flush().unwrap();
+        let mut fs = FAT32::open_rw(&path).unwrap();
+        assert_eq!(fs.read_directory(root).unwrap().len(), 2);
+        assert_eq!(fs.fat_lookup(3).unwrap(), 4);
+        assert_eq!(fs.fat_lookup(4).unwrap(), 3);
+        assert_eq!(fs.fat_lookup(5).unwrap(), 6);
+        assert_eq!(fs.fat_lookup(6).unwrap(), 0x0ffffff0);
+    }
+
+    #[test]
+    fn rename_entries() {
+        let mut fs = FAT32::open_rw(&TestImage::new(0x1000, 1).path("rename")).unwrap();
+        let root = fs.root_directory();
+        fs.write_file(root, "a.txt", b"a").unwrap();
+        let root = fs.root_directory();
+        fs.write_file(root, "b.txt", b"b").unwrap();
+
+        let root = fs.root_directory();
+        fs.rename_entry(root, "a.txt", "c.md").unwrap();
+        let root = fs.root_directory();
+        assert!(matches!(
+            fs.rename_entry(root, "c.md", "b.txt"),
+            Err(FatError::AlreadyExists)
+        ));
+        let root = fs.root_directory();
+        assert!(matches!(
+            fs.rename_entry(root, "c.md", "c?.md"),
+            Err(FatError::InvalidName)
+        ));
+
+        let file = file(&mut fs, "C.MD");
+        assert_eq!(fs.read_file(file).unwrap(), b"a");
+        let root = fs.root_directory();
+        assert_eq!(fs.read_directory(root).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn truncate_files() {
+        let path = TestImage::new(0x1000, 1).fs_info(1000).path("truncate");
+        let mut fs = FAT32::open_rw(&path).unwrap();
+        let data = content(2000);
+        let root = fs.root_directory();
+        let a = fs.write_file(root, "a.bin", &data).unwrap();
+        let root = fs.root_directory();
+        fs.write_file(root, "b.bin", &data).unwrap();
+
+        let root = fs.root_directory();
+        fs.truncate_file(root, "a.bin", 600).unwrap();
+        let file = file(&mut fs, "A.BIN");
+        assert_eq!(fs.read_file(file).unwrap(), &data[..600]);
+        assert_eq!(fs.fat_lookup(a.cluster + 1).unwrap(), 0x0fffffff);
+        assert_eq!(fs.fat_lookup(a.cluster + 2).unwrap(), 0);
+
+        let root = fs.root_directory();
+        fs.truncate_file(root, "b.bin", 0).unwrap();
+        let root = fs.root_directory();
+        let b = &fs.read_directory(root).unwrap()[1];
+        assert_eq!((b.cluster, b.size), (0, 0));
+
+        // 4 + 4 clusters, 2 + 4 freed
+        fs.flush().unwrap();
+        let image = fs::read(&path).unwrap();
+        assert_eq!(
+            LittleEndian::read_u32(&image[SECTOR_SIZE as usize + 488..]),
+            998
+        );
+    }
+
+    #[test]
+    fn append_to_files() {
+        let mut fs = FAT32::open_rw(&TestImage::new(0x1000, 1).path("append")).unwrap();
+        let data = content(3000);
+        for name in ["empty", "short", "full"].iter() {
+            let root = fs.root_directory();
+            fs.write_file(root, name, b"").unwrap();
+        }
+
+        // empty file, partial last cluster and full last cluster
+        let appends = [
+            ("empty", vec![0, 700]),
+            ("short", vec![10, 1990]),
+            ("full", vec![512, 1000]),
+        ];
+        for (name, sizes) in appends.iter() {
+            let mut size = 0;
+            for &len in sizes.iter() {
+                let root = fs.root_directory();
+                fs.append_to_file(root, name, &data[size..size + len])
+                    .unwrap();
+                size += len;
+            }
+            let file = file(&mut fs, &name.to_uppercase());
+            assert_eq!(fs.read_file(file).unwrap(), &data[..size]);
+        }
+    }
+
+    #[test]
+    fn pre_allocate_files() {
+        let mut fs = FAT32::open_rw(&TestImage::new(0x1000, 1).path("pre-allocate")).unwrap();
+        let data = content(2700);
+        let root = fs.root_directory();
+        let file = fs.pre_allocate(root, "DATA.BIN", 1500).unwrap();
+        assert_eq!(file.size, 0);
+        assert_eq!(fs.chain_to_vec(file.cluster).unwrap(), [3, 4, 5]);
+
+        // the reserved clusters are used first
+        fs.append_to_file(root, "DATA.BIN", &data[..700]).unwrap();
+        assert_eq!(fs.cluster_chain_length(file.cluster).unwrap(), 3);
+        fs.append_to_file(root, "DATA.BIN", &data[700..]).unwrap();
+        assert_eq!(fs.cluster_chain_length(file.cluster).unwrap(), 6);
+        let written = self::file(&mut fs, "DATA.BIN");
+        assert_eq!(fs.read_file(written).unwrap(), data);
+
+        let empty = fs.pre_allocate(root, "EMPTY.BIN", 0).unwrap();
+        assert_eq!((empty.cluster, empty.size), (0, 0));
+    }
+
+    #[test]
+    fn fsinfo_free_space() {
+        let path = TestImage::new(0x1000, 1).fs_info(100).path("fsinfo");
+        let mut fs = FAT32::open_rw(&path).unwrap();
+        assert_eq!(fs.free_space().unwrap(), 100 * 512);
+
+        // unknown count: found by scanning the FAT,
+        // all clusters but the root directory are free
+        let info = FsInfo {
+            free_clusters: 0xffffffff,
+            next_free: 3,
+        };
+        fs.write_fsinfo(&info).unwrap();
+        assert_eq!(fs.read_fsinfo().unwrap(), info);
+        let clusters = fs.total_cluster_count();
+        assert_eq!(fs.scan_free_clusters().unwrap(), clusters - 1);
+        assert_eq!(fs.free_space().unwrap(), (clusters as u64 - 1) * 512);
+    }
+
+    #[test]
+    fn iterate_directory() {
+        // root directory over 2 clusters, entries 0-15
+        // in cluster 2 and entry 16 in cluster 3
+        let mut image = TestImage::new(0x1000, 1);
+        image.chain(&[2, 3]);
+        for i in 0..17 {
+            let mut name = [0u8; 11];
+            name.copy_from_slice(format!("FILE{:<4}BIN", i).as_bytes());
+            image.entry(i, &name, 0x20, 0, 0);
+        }
+        let mut fs = image
+            .entry(1, b"\xe5ILE1   BIN", 0x20, 0, 0)
+            .open("iterate");
+
+        let root = fs.root_directory();
+        let names: Vec<String> = fs
+            .iter_directory(root)
+            .map(|entry| entry.unwrap().full_name())
+            .collect();
+        assert_eq!(names.len(), 16);
+        assert_eq!(names[0], "FILE0.BIN");
+        assert_eq!(names[1], "FILE2.BIN");
+        assert_eq!(names[15], "FILE16.BIN");
+
+        let root = fs.root_directory();
+        let entries = fs.read_directory(root).unwrap();
+        let root = fs.root_directory();
+        for (entry, other) in fs.iter_directory(root).zip(entries) {
+            assert_eq!(entry.unwrap().offset, other.offset);
+        }
+
+        // only the entries asked for are read
+        let root = fs.root_directory();
+        let mut iter = fs.iter_directory(root);
+        assert_eq!(iter.next().unwrap().unwrap().full_name(), "FILE0.BIN");
+        assert_eq!(iter.index, 1);
+    }
+
+    #[test]
+    fn filter_directory() {
+        let mut fs = TestImage::new(0x1000, 1)
+            .entry(0, b"README  TXT", 0x20, 0, 10)
+            .entry(1, b"DOCS       ", 0x10, 3, 0)
+            .entry(2, b"NOTES   TXT", 0x01, 0, 20)
+            .open("filter");
+
+        let root = fs.root_directory();
+        let files = fs
+            .read_directory_filtered(root, |entry| !entry.is_directory())
+            .unwrap();
+        let names: Vec<String> = files.iter().map(DirectoryEntry::full_name).collect();
+        assert_eq!(names, ["README.TXT", "NOTES.TXT"]);
+
+        let archives = fs
+            .read_directory_filtered(root, |entry| {
+                entry.attributes().contains(FileAttributes::ARCHIVE)
+            })
+            .unwrap();
+        assert_eq!(archives.len(), 1);
+        assert_eq!(archives[0].size, 10);
+    }
+
+    #[test]
+    fn glob_patterns() {
+        let matches =
+            |pattern: &str, name: &str| glob_match(&pattern.chars().collect::<Vec<_>>(), name);
+        assert!(matches("*", "README"));
+        assert!(matches("*", "A.TXT"));
+        assert!(matches("*.*", "A.TXT"));
+        assert!(!matches("*.*", "README"));
+        assert!(matches("*.txt", "NOTES.TXT"));
+        assert!(matches("?.T?T", "A.TXT"));
+        assert!(!matches("?.TXT", "AB.TXT"));
+        assert!(matches("*A*B*", "XAYYBZ"));
+        assert!(!matches("*A*B", "XAYYBZ"));
+        assert!(matches("", ""));
+        assert!(!matches("", "A"));
+
+        let mut fs = TestImage::new(0x1000, 1)
+            .entry(0, b"README  TXT", 0x20, 0, 0)
+            .entry(1, b"DOCS       ", 0x10, 3, 0)
+            .entry(2, b"NOTES   TXT", 0x20, 0, 0)
+            .open("glob");
+        let root = fs.root_directory();
+        let names = |entries: Vec<DirectoryEntry>| -> Vec<String> {
+            entries.iter().map(DirectoryEntry::full_name).collect()
+        };
+        assert_eq!(
+            names(fs.glob(root, "*.txt").unwrap()),
+            ["README.TXT", "NOTES.TXT"]
+        );
+        assert_eq!(names(fs.glob(root, "*").unwrap()).len(), 3);
+        assert_eq!(names(fs.glob(root, "d?cs").unwrap()), ["DOCS"]);
+    }
+
+    #[test]
+    fn read_file_ranges() {
+        let data = content(1300);
+        let mut image = TestImage::new(0x1000, 1);
+        image
+            .entry(0, b"DATA    BIN", 0x20, 3, 1300)
+            .chain(&[3, 5, 4])
+            .cluster(3, &data[..512])
+            .cluster(5, &data[512..1024])
+            .cluster(4, &data[1024..]);
+        let mut fs = image.open("ranges");
+        let file = file(&mut fs, "DATA.BIN");
+        let mut buf = [0u8; 600];
+
+        // inside a cluster, on a boundary, across clusters
+        assert_eq!(fs.read_file_at(file, 10, &mut buf[..100]).unwrap(), 100);
+        assert_eq!(buf[..100], data[10..110]);
+        assert_eq!(fs.read_file_at(file, 512, &mut buf[..10]).unwrap(), 10);
+        assert_eq!(buf[..10], data[512..522]);
+        assert_eq!(fs.read_file_at(file, 400, &mut buf).unwrap(), 600);
+        assert_eq!(buf[..], data[400..1000]);
+
+        // short read at the end, nothing past it
+        assert_eq!(fs.read_file_at(file, 1000, &mut buf).unwrap(), 300);
+        assert_eq!(buf[..300], data[1000..]);
+        assert_eq!(fs.read_file_at(file, 1300, &mut buf).unwrap(), 0);
+        assert_eq!(fs.read_file_at(file, 5000, &mut buf).unwrap(), 0);
+    }
+
+    #[test]
+    fn slack_space() {
+        let data = content(1024);
+        let mut image = TestImage::new(0x1000, 1);
+        image
+            .entry(0, b"DATA    BIN", 0x20, 3, 700)
+            .entry(1, b"FULL    BIN", 0x20, 5, 512)
+            .chain(&[3, 4])
+            .chain(&[5])
+            .cluster(3, &data[..512])
+            .cluster(4, &data[512..])
+            .cluster(5, &data[..512]);
+        let mut fs = image.open("slack");
+        let partial = file(&mut fs, "DATA.BIN");
+        let full = file(&mut fs, "FULL.BIN");
+        assert_eq!(fs.read_slack_space(partial).unwrap(), &data[700..]);
+        assert!(fs.read_slack_space(full).unwrap().is_empty());
+    }
+
+    #[test]
+    fn find_entries() {
+        let mut fs = TestImage::new(0x1000, 1)
+            .entry(0, b"README  TXT", 0x20, 0, 0)
+            .entry(1, b"DOCS       ", 0x10, 3, 0)
+            .open("find");
+
+        let root = fs.root_directory();
+        let entry = fs.find_entry(root, "readme.txt").unwrap().unwrap();
+        assert_eq!(entry.full_name(), "README.TXT");
+        let root = fs.root_directory();
+        assert!(fs.find_entry(root, "Docs").unwrap().unwrap().is_directory());
+        let root = fs.root_directory();
+        assert!(fs.find_entry(root, "MISSING").unwrap().is_none());
+    }
+
+    #[test]
+    fn entry_types() {
+        let mut fs = TestImage::new(0x1000, 1)
+            .entry(0, b"README  TXT", 0x20, 5, 42)
+            .entry(1, b"DOCS       ", 0x10, 3, 0)
+            .open("entry-types");
+        let root = fs.root_directory();
+        let entries = fs.read_directory(root).unwrap();
+        let file: EntryType = (&entries[0]).into();
+        assert_eq!(
+            file,
+            EntryType::File(File {
+                cluster: 5,
+                size: 42
+            })
+        );
+        assert_eq!(file, entries[0].entry_type());
+        let dir: EntryType = entries[1].clone().into();
+        assert_eq!(dir, EntryType::Dir(Directory { cluster: 3 }));
+    }
+
+    #[test]
+    fn open_paths() {
+        let data = content(100);
+        let mut fs = FAT32::open_rw(&TestImage::new(0x1000, 1).path("paths")).unwrap();
+        let root = fs.root_directory();
+        let a = fs.create_directory(root, "A").unwrap();
+        let b = fs.create_directory(a, "B").unwrap();
+        fs.write_file(b, "C.TXT", &data).unwrap();
+
+        match fs.open_path("/a/b/c.txt").unwrap() {
+            EntryType::File(file) => assert_eq!(fs.read_file(file).unwrap(), data),
+            EntryType::Dir(_) => panic!("C.TXT is a file"),
+        }
+        match fs.open_path("a//B/").unwrap() {
+            EntryType::Dir(dir) => assert_eq!(dir.cluster, b.cluster),
+            EntryType::File(_) => panic!("B is a directory"),
+        }
+        match fs.open_path("/A/..").unwrap() {
+            EntryType::Dir(dir) => assert_eq!(dir.cluster, 2),
+            EntryType::File(_) => panic!(".. is a directory"),
+        }
+        assert!(matches!(fs.open_path("/A/X"), Err(FatError::NotFound)));
+        assert!(matches!(
+            fs.open_path("/A/B/C.TXT/D"),
+            Err(FatError::NotADirectory)
+        ));
+    }
+
+    #[test]
+    fn display_entries() {
+        let mut fs = TestImage::new(0x1000, 1)
+            .entry(0, b"README  TXT", 0x21, 3, 1234)
+            .entry(1, b"DOCS       ", 0x10, 4, 0)
+            .open("display");
+        let root = fs.root_directory();
+        let entries = fs.read_directory(root).unwrap();
+
+        assert_eq!(
+            entries[0].to_string(),
+            "-r--r--r--       1234  1980-00-00  README.TXT"
+        );
+        assert_eq!(
+            entries[1].to_string(),
+            "drwxr-xr-x      <DIR>  1980-00-00  DOCS"
+        );
+    }
+
+    #[test]
+    fn check_integrity() {
+        let mut fs = FAT32::open_rw(&TestImage::new(0x1000, 1).path("check")).unwrap();
+        let root = fs.root_directory();
+        let dir = fs.create_directory(root, "DIR").unwrap();
+        fs.write_file(dir, "DATA.BIN", &content(1300)).unwrap();
+        fs.write_file(root, "EMPTY.BIN", &[]).unwrap();
+        assert_eq!(fs.check_integrity().unwrap(), vec![]);
+
+        let mut fs = TestImage::new(0x1000, 1)
+            .entry(0, b"GOOD    BIN", 0x20, 3, 600)
+            .chain(&[3, 4])
+            .entry(1, b"CROSS   BIN", 0x20, 5, 600)
+            .chain(&[5, 4])
+            .entry(2, b"SHORT   BIN", 0x20, 6, 2000)
+            .chain(&[6])
+            .entry(3, b"LOOP       ", 0x10, 7, 0)
+            .fat_entry(0, 7, 8)
+            .fat_entry(0, 8, 7)
+            .entry(4, b"BAD     BIN", 0x20, 9, 100)
+            .fat_entry(0, 9, 0x0ffffff7)
+            .chain(&[10])
+            .entry(5, b"OUT     BIN", 0x20, 11, 100)
+            .fat_entry(0, 11, 0x0ffffff0)
+            .open("corrupt");
+
+        let path = String::from;
+        assert_eq!(
+            fs.check_integrity().unwrap(),
+            vec![
+                IntegrityError::CrossLinked {
+                    path: path("/CROSS.BIN"),
+                    other: path("/GOOD.BIN"),
+                    cluster: 4
+                },
+                IntegrityError::SizeMismatch {
+                    path: path("/SHORT.BIN"),
+                    size: 2000,
+                    clusters: 1
+                },
+                IntegrityError::BadCluster {
+                    path: path("/BAD.BIN"),
+                    cluster: 9
+                },
+                IntegrityError::InvalidCluster {
+                    path: path("/OUT.BIN"),
+                    cluster: 0x0ffffff0
+                },
+                IntegrityError::CyclicChain {
+                    path: path("/LOOP"),
+                    cluster: 7
+                },
+                IntegrityError::OrphanedCluster(10),
+            ]
+        );
+    }
+
+    #[test]
+    fn repairs() {
+        let image = TestImage::new(0x1000, 1)
+            .entry(0, b"GOOD    BIN", 0x20, 3, 600)
+            .chain(&[3, 4])
+            .entry(1, b"CROSS   BIN", 0x20, 5, 600)
+            .chain(&[5, 4])
+            .entry(2, b"FIRST   BIN", 0x20, 3, 100)
+            .entry(3, b"SHORT   BIN", 0x20, 6, 2000)
+            .chain(&[6])
+            .entry(4, b"OUT     BIN", 0x20, 11, 600)
+            .fat_entry(0, 11, 0x0ffffff0)
+            .entry(5, b"NOWHERE BIN", 0x20, 0x0ffffff0, 100)
+            .entry(6, b"MIDDLE  BIN", 0x20, 14, 1500)
+            .chain(&[14, 15])
+            .fat_entry(0, 15, 1)
+            .chain(&[10])
+            .chain(&[12, 13])
+            .path("repair");
+        let mut fs = FAT32::open_rw(&image).unwrap();
+        assert_eq!(
+            fs.repair().unwrap(),
+            RepairReport {
+                orphans_freed: 3,
+                cross_links_broken: 2,
+                invalid_links_cut: 3,
+                sizes_corrected: 6,
+                changes_written: 14,
+            }
+        );
+        fs.flush().unwrap();
+
+        let mut fs = FAT32::open_rw(&image).unwrap();
+        assert_eq!(fs.check_integrity().unwrap(), vec![]);
+        assert_eq!(fs.chain_to_vec(5).unwrap(), [5]);
+        assert_eq!(fs.chain_to_vec(3).unwrap(), [3, 4]);
+        assert_eq!(fs.stat("/CROSS.BIN").unwrap().size, 512);
+        assert_eq!(fs.stat("/FIRST.BIN").unwrap().size, 0);
+        assert_eq!(fs.stat("/SHORT.BIN").unwrap().size, 512);
+        // cut after the last cluster that exists, and sized from it
+        assert_eq!(fs.fat_lookup(11).unwrap(), 0x0fffffff);
+        assert_eq!(fs.stat("/OUT.BIN").unwrap().size, 512);
+        assert_eq!(fs.stat("/NOWHERE.BIN").unwrap().first_cluster, 0);
+        assert_eq!(fs.stat("/NOWHERE.BIN").unwrap().size, 0);
+        assert_eq!(fs.chain_to_vec(14).unwrap(), [14, 15]);
+        assert_eq!(fs.fat_lookup(15).unwrap(), 0x0fffffff);
+        assert_eq!(fs.stat("/MIDDLE.BIN").unwrap().size, 1024);
+        assert_eq!(fs.fat_lookup(10).unwrap(), 0);
+        assert_eq!(fs.repair().unwrap(), RepairReport::default());
+    }
+
+    #[test]
+    fn unseekable_input() {
+        let data = TestImage::new(0x1000, 1)
+            .entry(0, b"A       TXT", 0x20, 3, 5)
+            .chain(&[3])
+            .cluster(3, b"hello")
+            .data
+            .clone();
+        // a reader without Seek, as stdin
+        let reader = io::Read::chain(&data[..512], &data[512..]);
+        let mut fs = FAT32::from_unseekable(reader).unwrap();
+        let a = file(&mut fs, "A.TXT");
+        assert_eq!(fs.read_file(a).unwrap(), b"hello");
+        assert!(FAT32::from_unseekable(&data[..50]).is_err());
+    }
+
+    #[test]
+    fn format_images() {
+        let mut fs = FAT32::format(io::Cursor::new(Vec::new()), "FRESH", 33 << 20).unwrap();
+        assert_eq!(fs.volume_name(), "FRESH");
+        assert_eq!(fs.volume_size(), 33 << 20);
+        assert!(fs.total_cluster_count() >= 65525);
+        let free = fs.free_cluster_count().unwrap();
+        assert_eq!(free, fs.scan_free_clusters().unwrap());
+        assert_eq!(free, fs.total_cluster_count() - 1);
+
+        let data = content(3000);
+        let root = fs.root_directory();
+        assert!(fs.read_directory(root).unwrap().is_empty());
+        let dir = fs.create_directory(root, "DIR").unwrap();
+        let file = fs.write_file(dir, "DATA.BIN", &data).unwrap();
+        assert_eq!(fs.read_file(file).unwrap(), data);
+        assert_eq!(fs.check_integrity().unwrap(), vec![]);
+
+        let fs = FAT32::format(io::Cursor::new(Vec::new()), "", 33 << 20).unwrap();
+        assert_eq!(fs.volume_name(), "NO NAME");
+        assert!(matches!(
+            FAT32::format(io::Cursor::new(Vec::new()), "LONGER LABEL", 33 << 20),
+            Err(FatError::InvalidName)
+        ));
+        for size in [16 << 10, 8 << 20, 66_600 * 512] {
+            assert!(matches!(
+                FAT32::format(io::Cursor::new(Vec::new()), "TINY", size),
+                Err(FatError::VolumeTooSmall)
+            ));
+        }
+    }
+
+    #[test]
+    fn format_cluster_counts() {
+        // every accepted size gives a FAT32 cluster count, checked
+        // around each row of the cluster size table and over the
+        // whole range; the FATs must hold every cluster
+        let mut sizes: Vec<u32> = (0..=u32::MAX).step_by(9973).collect();
+        for limit in [
+            66_600,
+            532_480,
+            16_777_216,
+            33_554_432,
+            67_108_864,
+            u32::MAX - 8,
+        ] {
+            sizes.extend(limit - 8..=limit + 8);
+        }
+        for sector_count in sizes {
+            if let Ok((cluster_size, fat_size)) = format_geometry(sector_count) {
+                let clusters = (sector_count - 32 - 2 * fat_size) / cluster_size;
+                assert!(clusters >= 65525, "{} sectors", sector_count);
+                assert!(fat_size as u64 * 128 >= clusters as u64 + 2);
+            } else {
+                assert!(sector_count <= 66_600, "{} sectors", sector_count);
+            }
+        }
+
+        let smallest = 66_601 * 512;
+        let fs = FAT32::format(io::Cursor::new(Vec::new()), "SMALL", smallest).unwrap();
+        assert!(fs.total_cluster_count() >= 65525);
+    }
+
+    #[test]
+    fn set_volume_labels() {
+        let path = TestImage::new(0x1000, 1)
+            .entry(0, b"README  TXT", 0x20, 0, 0)
+            .path("label");
+        let mut fs = FAT32::open_rw(&path).unwrap();
+        let labels = |fs: &mut FAT32<fs::File, ReadWrite>| -> Vec<String> {
+            let root = fs.root_directory();
+            let mut entries = fs.iter_directory(root);
+            entries.with_labels = true;
+            entries
+                .map(Result::unwrap)
+                .filter(|entry| entry.is_volume_label())
+                .map(|entry| entry.full_name())
+                .collect()
+        };
+
+        fs.set_volume_label("MY DISK").unwrap();
+        assert_eq!(fs.volume_name(), "MY DISK");
+        assert_eq!(labels(&mut fs), ["MY DISK"]);
+        // not listed with the files
+        let root = fs.root_directory();
+        assert_eq!(fs.read_directory(root).unwrap().len(), 1);
+        assert_eq!(
+            fs.volume_label_from_root_dir().unwrap().as_deref(),
+            Some("MY DISK")
+        );
+
+        // updated in place
+        fs.set_volume_label("OTHER").unwrap();
+        assert_eq!(labels(&mut fs), ["OTHER"]);
+        fs.flush().unwrap();
+        let mut fs = FAT32::open_rw(&path).unwrap();
+        assert_eq!(fs.volume_name(), "OTHER");
+
+        fs.set_volume_label("").unwrap();
+        assert_eq!(fs.volume_name(), "");
+        assert!(labels(&mut fs).is_empty());
+        assert_eq!(fs.volume_label_from_root_dir().unwrap(), None);
+        assert_eq!(fs.read_directory(fs.root_directory()).unwrap().len(), 1);
+        assert!(matches!(
+            fs.set_volume_label("bad\nlabel"),
+            Err(FatError::InvalidName)
+        ));
+
+        // formatted volumes have a backup boot sector to keep in sync
+        let mut fs = FAT32::format(io::Cursor::new(Vec::new()), "FRESH", 33 << 20).unwrap();
+        fs.set_volume_label("MY DISK").unwrap();
+        assert_eq!(fs.backup_boot_sector().unwrap(), *fs.boot_sector());
+        assert_eq!(
+            fs.backup_boot_sector().unwrap().volume_label,
+            *b"MY DISK    "
+        );
+        fs.set_volume_label("").unwrap();
+        assert_eq!(fs.backup_boot_sector().unwrap(), *fs.boot_sector());
+    }
+
+    #[test]
+    fn long_name_sequences() {
+        // one LFN part holding "a.txt", for the short name "A       TXT"
+        let short = *b"A       TXT";
+        let checksum = short
+            .iter()
+            .fold(0u8, |sum, &c| sum.rotate_right(1).wrapping_add(c));
+        let part = |first: u8| {
+            let mut raw = [0u8; 32];
+            raw[0] = first;
+            raw[11] = 0x0f;
+            raw[13] = checksum;
+            for (i, c) in "a.txt".encode_utf16().enumerate() {
+                LittleEndian::write_u16(&mut raw[1 + 2 * i..], c);
+            }
+            raw
+        };
+
+        let lfn = LongName::push(None, &part(0x41), 32).unwrap();
+        let (name, offsets) = lfn.finish(&short).unwrap();
+        assert_eq!(name, "a.txt");
+        assert_eq!(offsets, [32]);
+
+        // a part numbered 0 after a complete run is no continuation
+        let lfn = LongName::push(None, &part(0x41), 0);
+        assert!(LongName::push(lfn, &part(0x20), 32).is_none());
+        let lfn = LongName::push(None, &part(0x41), 0);
+        assert!(LongName::push(lfn, &part(0x00), 32).is_none());
+        // neither is a part out of sequence
+        let lfn = LongName::push(None, &part(0x42), 0);
+        assert!(LongName::push(lfn, &part(0x02), 32).is_none());
+        assert!(LongName::push(None, &part(0x40), 0).is_none());
+    }
+
+    #[test]
+    fn stat_files() {
+        let mut fs = FAT32::open_rw(&TestImage::new(0x1000, 1).path("stat")).unwrap();
+        let root = fs.root_directory();
+        let dir = fs.create_directory(root, "DIR").unwrap();
+        let file = fs.write_file(dir, "DATA.BIN", &content(1300)).unwrap();
+
+        let stat = fs.stat("/dir/data.bin").unwrap();
+        assert_eq!(stat.name, "DATA.BIN");
+        assert_eq!(stat.long_name, None);
+        assert_eq!(stat.size, 1300);
+        assert_eq!(stat.attributes, FileAttributes::ARCHIVE);
+        assert_eq!(stat.first_cluster, file.cluster);
+
+        assert!(matches!(fs.stat("/DIR"), Err(FatError::IsDirectory)));
+        assert!(matches!(fs.stat("/"), Err(FatError::IsDirectory)));
+        assert!(matches!(fs.stat("/DIR/NONE"), Err(FatError::NotFound)));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serialize_metadata() {
+        let mut image = TestImage::new(0x1000, 1);
+        image.entry(0, b"DATA    BIN", 0x21, 0, 0);
+        let mut fs = image.open("serde");
+        let mut stat = fs.stat("/DATA.BIN").unwrap();
+        stat.modified = Timestamp::from_fat_date_time((41 << 9) | (3 << 5) | 14, 15 << 11, 0);
+
+        let json = serde_json::to_value(&stat).unwrap();
+        assert_eq!(json["modified"], "2021-03-14T15:00:00.00");
+        assert_eq!(json["attributes"]["read_only"], true);
+        assert_eq!(json["attributes"]["archive"], true);
+        assert_eq!(json["attributes"]["hidden"], false);
+        assert_eq!(serde_json::from_value::<FileStat>(json).unwrap(), stat);
+
+        let root = fs.root_directory();
+        let entry = &fs.read_directory(root).unwrap()[0];
+        let json = serde_json::to_value(entry).unwrap();
+        assert!(json.get("offset").is_none());
+        assert!(json.get("long_name_offsets").is_none());
+        let entry: DirectoryEntry = serde_json::from_value(json).unwrap();
+        assert_eq!(entry.full_name(), "DATA.BIN");
+        assert_eq!(entry.offset, 0);
+        assert!(entry.long_name_offsets.is_empty());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn export_trees() {
+        let mut fs = FAT32::open_rw(&TestImage::new(0x1000, 1).path("export")).unwrap();
+        let root = fs.root_directory();
+        let dir = fs.create_directory(root, "DIR").unwrap();
+        let sub = fs.create_directory(dir, "SUB").unwrap();
+        fs.write_file(sub, "C.BIN", &content(10)).unwrap();
+        fs.write_file(root, "A.BIN", &content(1000)).unwrap();
+
+        let tree = fs.export_directory_tree().unwrap();
+        assert_eq!(tree["A.BIN"]["size"], 1000);
+        assert_eq!(tree["DIR"]["SUB"]["C.BIN"]["size"], 10);
+        assert_eq!(tree["DIR"].as_object().unwrap().len(), 1);
+        assert_eq!(tree.as_object().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn disk_usage() {
+        let mut fs = FAT32::open_rw(&TestImage::new(0x1000, 1).path("usage")).unwrap();
+        let root = fs.root_directory();
+        let dir = fs.create_directory(root, "DIR").unwrap();
+        let sub = fs.create_directory(dir, "SUB").unwrap();
+        fs.write_file(root, "A.BIN", &content(100)).unwrap();
+        fs.write_file(dir, "B.BIN", &content(1300)).unwrap();
+        fs.write_file(sub, "C.BIN", &content(512)).unwrap();
+        fs.write_file(sub, "D.BIN", &[]).unwrap();
+
+        assert_eq!(fs.disk_usage(root).unwrap(), 100 + 1300 + 512);
+        assert_eq!(fs.allocated_space(root).unwrap(), (1 + 3 + 1) * 512);
+        assert_eq!(fs.disk_usage(dir).unwrap(), 1300 + 512);
+        assert_eq!(fs.allocated_space(sub).unwrap(), 512);
+    }
+
+    #[test]
+    fn walk_tree() {
+        let mut fs = FAT32::open_rw(&TestImage::new(0x1000, 1).path("walk")).unwrap();
+        let root = fs.root_directory();
+        let dir = fs.create_directory(root, "DIR").unwrap();
+        let sub = fs.create_directory(dir, "SUB").unwrap();
+        fs.write_file(sub, "C.BIN", &[]).unwrap();
+        fs.write_file(dir, "B.BIN", &[]).unwrap();
+        fs.write_file(root, "A.BIN", &[]).unwrap();
+
+        let paths: Vec<String> = fs.walk(root).map(|item| item.unwrap().path).collect();
+        assert_eq!(
+            paths,
+            ["/DIR", "/DIR/SUB", "/DIR/SUB/C.BIN", "/DIR/B.BIN", "/A.BIN"]
+        );
+        let paths: Vec<String> = fs.walk(dir).map(|item| item.unwrap().path).collect();
+        assert_eq!(paths, ["/SUB", "/SUB/C.BIN", "/B.BIN"]);
+    }
+
+    #[test]
+    fn diff_volumes() {
+        let path = TestImage::new(0x1000, 1).path("diff-old");
+        let mut old = FAT32::open_rw(&path).unwrap();
+        let root = old.root_directory();
+        let dir = old.create_directory(root, "DIR").unwrap();
+        old.write_file(dir, "SAME.BIN", b"same").unwrap();
+        old.write_file(dir, "EDIT.BIN", b"before").unwrap();
+        old.write_file(root, "GROW.BIN", b"small").unwrap();
+        old.write_file(root, "GONE.BIN", b"gone").unwrap();
+        old.write_file(root, "KIND", b"file").unwrap();
+
+        let mut new = FAT32::open_rw(&TestImage::new(0x1000, 1).path("diff-new")).unwrap();
+        let root = new.root_directory();
+        let dir = new.create_directory(root, "dir").unwrap();
+        new.write_file(dir, "same.bin", b"same").unwrap();
+        new.write_file(dir, "EDIT.BIN", b"after!").unwrap();
+        new.write_file(root, "GROW.BIN", b"bigger").unwrap();
+        new.write_file(root, "NEW.BIN", b"new").unwrap();
+        new.create_directory(root, "KIND").unwrap();
+
+        let describe = |changes: Vec<DiffEntry>| -> Vec<String> {
+            changes
+                .into_iter()
+                .map(|change| match change {
+                    DiffEntry::Added(item) => format!("+{}", item.path),
+                    DiffEntry::Removed(item) => format!("-{}", item.path),
+                    DiffEntry::Modified {
+                        path,
+                        old_size,
+                        new_size,
+                        content_changed,
+                    } => format!("~{} {} {} {}", path, old_size, new_size, content_changed),
+                })
+                .collect()
+        };
+        let changes = old.diff(&mut new, DiffOptions::default()).unwrap();
+        assert_eq!(
+            describe(changes),
+            [
+                "-/GONE.BIN",
+                "~/GROW.BIN 5 6 true",
+                "-/KIND",
+                "+/KIND",
+                "+/NEW.BIN"
+            ]
+        );
+        let options = DiffOptions {
+            compare_content: true,
+        };
+        let changes = old.diff(&mut new, options).unwrap();
+        assert_eq!(describe(changes)[0], "~/DIR/EDIT.BIN 6 6 true");
+        old.flush().unwrap();
+        let mut copy = FAT32::new(&path).unwrap();
+        assert!(old.diff(&mut copy, options).unwrap().is_empty());
+    }
+
+    #[test]
+    fn extract_tree() {
+        let mut fs = FAT32::open_rw(&TestImage::new(0x1000, 1).path("extract")).unwrap();
+        let root = fs.root_directory();
+        let dir = fs.create_directory(root, "DIR").unwrap();
+        fs.create_directory(dir, "EMPTY").unwrap();
+        fs.write_file(dir, "DATA.BIN", &content(1300)).unwrap();
+        fs.write_file(root, "A.TXT", b"hello").unwrap();
+
+        let dest = std::env::temp_dir().join("fat-rs-extract");
+        let _ = fs::remove_dir_all(&dest);
+        fs.extract_to_directory(&dest, ExtractOptions::default())
+            .unwrap();
+        assert_eq!(fs::read(dest.join("A.TXT")).unwrap(), b"hello");
+        assert_eq!(fs::read(dest.join("DIR/DATA.BIN")).unwrap(), content(1300));
+        assert!(dest.join("DIR/EMPTY").is_dir());
+
+        fs::write(dest.join("A.TXT"), b"host").unwrap();
+        assert!(matches!(
+            fs.extract_to_directory(&dest, ExtractOptions::default()),
+            Err(FatError::AlreadyExists)
+        ));
+        let options = ExtractOptions { overwrite: true };
+        fs.extract_to_directory(&dest, options).unwrap();
+        assert_eq!(fs::read(dest.join("A.TXT")).unwrap(), b"hello");
+        fs::remove_dir_all(&dest).unwrap();
+    }
+
+    #[test]
+    fn import_tree() {
+        let src = std::env::temp_dir().join("fat-rs-import");
+        let _ = fs::remove_dir_all(&src);
+        fs::create_dir_all(src.join("dir/sub")).unwrap();
+        fs::write(src.join("a.txt"), b"hello").unwrap();
+        fs::write(src.join(".hidden"), b"").unwrap();
+        fs::write(src.join("dir/data.bin"), content(1300)).unwrap();
+        fs::write(src.join("dir/sub/b.txt"), b"b").unwrap();
+
+        let mut fs = FAT32::open_rw(&TestImage::new(0x1000, 1).path("import")).unwrap();
+        let root = fs.root_directory();
+        fs.write_file(root, "A.TXT", b"old").unwrap();
+        assert!(matches!(
+            fs.import_from_directory(&src, ImportOptions::default()),
+            Err(FatError::InvalidNames(names)) if names == [".hidden"]
+        ));
+        let options = ImportOptions {
+            overwrite: false,
+            skip_hidden: true,
+        };
+        fs.import_from_directory(&src, options).unwrap();
+        let a = file(&mut fs, "A.TXT");
+        assert_eq!(fs.read_file(a).unwrap(), b"old");
+        assert_eq!(fs.stat("/DIR/DATA.BIN").unwrap().size, 1300);
+        assert!(fs.is_file("/DIR/SUB/B.TXT").unwrap());
+
+        let options = ImportOptions {
+            overwrite: true,
+            skip_hidden: true,
+        };
+        fs.import_from_directory(&src, options).unwrap();
+        let a = file(&mut fs, "A.TXT");
+        assert_eq!(fs.read_file(a).unwrap(), b"hello");
+        assert_eq!(fs.check_integrity().unwrap(), vec![]);
+
+        fs::write(src.join("long file name.txt"), b"").unwrap();
+        assert!(matches!(
+            fs.import_from_directory(&src, options),
+            Err(FatError::InvalidNames(names)) if names == ["long file name.txt"]
+        ));
+        fs::remove_dir_all(&src).unwrap();
+    }
+
+    #[cfg(feature = "hashes")]
+    #[test]
+    fn file_hashes() {
+        let mut fs = FAT32::open_rw(&TestImage::new(0x1000, 1).path("hashes")).unwrap();
+        let root = fs.root_directory();
+        let dir = fs.create_directory(root, "DIR").unwrap();
+        let empty = fs.write_file(root, "EMPTY", &[]).unwrap();
+        fs.write_file(dir, "ABC.TXT", b"abc").unwrap();
+
+        let hex = |digest: [u8; 32]| -> String {
+            digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+        };
+        assert_eq!(
+            hex(fs.hash_file(empty).unwrap()),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        let hashes = fs.hash_directory_tree(root).unwrap();
+        assert_eq!(
+            hashes.keys().collect::<Vec<_>>(),
+            ["/DIR/ABC.TXT", "/EMPTY"]
+        );
+        assert_eq!(
+            hex(hashes["/DIR/ABC.TXT"]),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        let big = fs.write_file(dir, "BIG.BIN", &content(5000)).unwrap();
+        let expected: [u8; 32] = <sha2::Sha256 as sha2::Digest>::digest(content(5000)).into();
+        assert_eq!(fs.hash_file(big).unwrap(), expected);
+    }
+
+    #[test]
+    fn entry_counts() {
+        let mut fs = FAT32::open_rw(&TestImage::new(0x1000, 1).path("counts")).unwrap();
+        let root = fs.root_directory();
+        assert_eq!(fs.count_entries(root).unwrap(), (0, 0));
+        let dir = fs.create_directory(root, "DIR").unwrap();
+        fs.create_directory(dir, "SUB").unwrap();
+        for i in 0..20 {
+            fs.write_file(dir, &format!("F{}.BIN", i), &[]).unwrap();
+        }
+        fs.write_file(root, "A.BIN", &[]).unwrap();
+        assert_eq!(fs.count_entries(root).unwrap(), (1, 1));
+        assert_eq!(fs.count_entries(dir).unwrap(), (20, 1));
+    }
+
+    #[test]
+    fn file_attributes() {
+        let path = TestImage::new(0x1000, 1).path("attributes");
+        let mut fs = FAT32::open_rw(&path).unwrap();
+        let root = fs.root_directory();
+        let dir = fs.create_directory(root, "DIR").unwrap();
+        fs.write_file(dir, "A.TXT", b"a").unwrap();
+
+        fs.set_file_attributes("/DIR/A.TXT", FileAttributes::empty())
+            .unwrap();
+        let hidden = FileAttributes::HIDDEN | FileAttributes::READ_ONLY;
+        fs.set_file_attributes("/DIR", hidden).unwrap();
+        for attrs in [FileAttributes::DIRECTORY, FileAttributes::VOLUME_LABEL] {
+            assert!(matches!(
+                fs.set_file_attributes("/DIR/A.TXT", attrs | FileAttributes::ARCHIVE),
+                Err(FatError::InvalidOperation)
+            ));
+        }
+        assert!(matches!(
+            fs.set_file_attributes("/", hidden),
+            Err(FatError::InvalidOperation)
+        ));
+        assert!(matches!(
+            fs.set_file_attributes("/B.TXT", hidden),
+            Err(FatError::NotFound)
+        ));
+
+        // already written while `fs` is still open
+        let file_offset = fs.path_entry("/DIR/A.TXT").unwrap().unwrap().offset;
+        let dir_offset = fs.path_entry("/DIR").unwrap().unwrap().offset;
+        let image = fs::read(&path).unwrap();
+        assert_eq!(image[file_offset as usize + 11], 0);
+        assert_eq!(
+            image[dir_offset as usize + 11],
+            (hidden | FileAttributes::DIRECTORY).bits()
+        );
+        assert_eq!(
+            fs.stat("/DIR/A.TXT").unwrap().attributes,
+            FileAttributes::empty()
+        );
+        assert_eq!(fs.stat("/DIR/A.TXT").unwrap().size, 1);
+    }
+
+    #[test]
+    fn file_timestamps() {
+        let path = TestImage::new(0x1000, 1).path("timestamps");
+        let mut fs = FAT32::open_rw(&path).unwrap();
+        let root = fs.root_directory();
+        fs.write_file(root, "A.TXT", b"a").unwrap();
+
+        let created = Timestamp {
+            year: 2021,
+            month: 3,
+            day: 14,
+            hour: 15,
+            minute: 9,
+            second: 27,
+            centisecond: 65,
+        };
+        let modified = Timestamp {
+            year: 2023,
+            month: 12,
+            day: 31,
+            hour: 23,
+            minute: 59,
+            second: 58,
+            centisecond: 0,
+        };
+        fs.set_file_timestamps("/A.TXT", created, modified).unwrap();
+        let invalid = Timestamp {
+            month: 13,
+            ..modified
+        };
+        assert!(matches!(
+            fs.set_file_timestamps("/A.TXT", created, invalid),
+            Err(FatError::InvalidTimestamp)
+        ));
+
+        // already written while `fs` is still open
+        let offset = fs.path_entry("/A.TXT").unwrap().unwrap().offset as usize;
+        let image = fs::read(&path).unwrap();
+        let (date, time, tenths) = created.to_fat_date_time();
+        assert_eq!(image[offset + 13], tenths);
+        assert_eq!(LittleEndian::read_u16(&image[offset + 14..]), time);
+        assert_eq!(LittleEndian::read_u16(&image[offset + 16..]), date);
+        let (date, time, _) = modified.to_fat_date_time();
+        assert_eq!(LittleEndian::read_u16(&image[offset + 22..]), time);
+        assert_eq!(LittleEndian::read_u16(&image[offset + 24..]), date);
+
+        let stat = fs.stat("/A.TXT").unwrap();
+        assert_eq!(stat.created, created);
+        assert_eq!(stat.modified, modified);
+        assert_eq!(stat.size, 1);
+    }
+
+    #[test]
+    fn entry_offsets() {
+        let mut data = TestImage::new(0x1000, 1)
+            .entry(0, b"A       TXT", 0x20, 0, 0)
+            .entry(1, b"MIDDLE  TXT", 0x20, 0, 0)
+            .entry(2, b"B       TXT", 0x20, 0, 0)
+            .data
+            .clone();
+        let mut fs = FAT32::from_bytes(&data).unwrap();
+        let root = fs.root_directory();
+        let offset = fs.directory_entry_offset(root, "b.txt").unwrap();
+        assert_eq!(
+            fs.directory_entry_offset(root, "A.TXT").unwrap(),
+            offset - 2 * 32
+        );
+        assert!(matches!(
+            fs.directory_entry_offset(root, "C.TXT"),
+            Err(FatError::NotFound)
+        ));
+
+        drop(fs);
+        data[offset as usize + 11] = 0x01;
+        let mut fs = FAT32::from_bytes(&data).unwrap();
+        let entry = fs.find_entry(root, "B.TXT").unwrap().unwrap();
+        assert_eq!(entry.attributes(), FileAttributes::READ_ONLY);
+    }
+
+    #[test]
+    fn expand_directories() {
+        let mut fs = FAT32::open_rw(&TestImage::new(0x1000, 1).path("expand")).unwrap();
+        let root = fs.root_directory();
+        let dir = fs.create_directory(root, "DIR").unwrap();
+        let first = fs.chain_to_vec(dir.cluster).unwrap();
+        let data = fs.write_file(root, "DATA.BIN", &content(512)).unwrap();
+        fs.write_cluster(data.cluster + 1, &content(512)).unwrap();
+
+        let new = fs.expand_directory(dir).unwrap();
+        assert_eq!(new, data.cluster + 1);
+        assert_eq!(fs.chain_to_vec(dir.cluster).unwrap(), [first[0], new]);
+        assert_eq!(fs.read_cluster(new).unwrap(), vec![0u8; 512]);
+        // `.` and `..` only, the new cluster starts with an end marker
+        assert_eq!(fs.read_directory(dir).unwrap().len(), 2);
+        for i in 0..20 {
+            fs.write_file(dir, &format!("F{}.BIN", i), &[]).unwrap();
+        }
+        assert_eq!(fs.chain_to_vec(dir.cluster).unwrap().len(), 2);
+        assert_eq!(fs.check_integrity().unwrap(), vec![]);
+    }
+
+    #[test]
+    fn raw_clusters() {
+        let data = content(512);
+        let path = TestImage::new(0x1000, 1).cluster(5, &data).path("raw");
+        let mut fs = FAT32::open_rw(&path).unwrap();
+        assert_eq!(fs.read_cluster(5).unwrap(), data);
+
+        let zeros = vec![0u8; 512];
+        fs.write_cluster(5, &zeros).unwrap();
+        assert_eq!(fs.read_cluster(5).unwrap(), zeros);
+        assert!(matches!(
+            fs.write_cluster(5, &data[..100]),
+            Err(FatError::InvalidOperation)
+        ));
+
+        let max = fs.total_cluster_count() + 1;
+        fs.read_cluster(max).unwrap();
+        for cluster in [0, 1, max + 1] {
+            assert!(matches!(
+                fs.read_cluster(cluster),
+                Err(FatError::InvalidCluster { max: m, .. }) if m == max
+            ));
+        }
+    }
+
+    #[test]
+    fn cluster_geometry() {
+        // 32 reserved sectors and a 33 sectors FAT
+        let mut image = TestImage::new(0x1000, 1);
+        image.entry(0, b"FAR     BIN", 0x20, 0x2000, 10);
+        let mut fs = image.open("geometry");
+        assert_eq!(fs.total_cluster_count(), 0x1000 - 65);
+        assert_eq!(fs.cluster_start(2).unwrap(), 65);
+        assert_eq!(fs.cluster_start(0x1000 - 64).unwrap(), 0x1000 - 1);
+
+        let max = 0x1000 - 64;
+        assert!(matches!(
+            fs.cluster_start(max + 1),
+            Err(FatError::InvalidCluster { cluster, max: m }) if cluster == max + 1 && m == max
+        ));
+        assert!(matches!(
+            fs.cluster_start(1),
+            Err(FatError::InvalidCluster { cluster: 1, .. })
+        ));
+        // a file pointing past the data region
+        let file = file(&mut fs, "FAR.BIN");
+        assert!(matches!(
+            fs.read_file(file),
+            Err(FatError::InvalidCluster {
+                cluster: 0x2000,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn free_space_maps() {
+        let mut image = TestImage::new(0x1000, 1);
+        image.chain(&[3]).chain(&[5, 6]);
+        let path = image.path("free-map");
+        let mut fs = FAT32::open_rw(&path).unwrap();
+        let map = fs.free_space_map().unwrap();
+        let total = fs.total_cluster_count();
+        assert_eq!(map.free_count(), total - 4);
+        assert_eq!(fs.scan_free_clusters().unwrap(), total - 4);
+        assert!(!map.is_free(3) && map.is_free(4) && !map.is_free(6));
+        assert!(!map.is_free(0) && !map.is_free(total + 2));
+        assert_eq!(map.first_free(), Some(4));
+        assert_eq!(map.find_contiguous(1), Some(4));
+        assert_eq!(map.find_contiguous(3), Some(7));
+        assert_eq!(map.find_contiguous(total), None);
+
+        // new files go in a single run of clusters
+        let root = fs.root_directory();
+        let written = fs.write_file(root, "RUN.BIN", &content(1000)).unwrap();
+        assert_eq!(fs.chain_to_vec(written.cluster).unwrap(), [7, 8]);
+        assert_eq!(fs.free_space_map().unwrap().first_free(), Some(4));
+    }
+
+    #[test]
+    fn cluster_stats() {
+        let mut image = TestImage::new(0x1000, 1);
+        image.chain(&[3, 4]).chain(&[6]);
+        LittleEndian::write_u32(
+            &mut image.data[32 * SECTOR_SIZE as usize + 5 * 4..],
+            BAD_CLUSTER,
+        );
+        let mut fs = image.open("stats");
+        let total = fs.total_cluster_count() + 2;
+        assert_eq!(
+            fs.cluster_statistics().unwrap(),
+            ClusterStats {
+                total,
+                free: total - 7,
+                bad: 1,
+                reserved: 2,
+                used: 4,
+            }
+        );
+    }
+
+    #[test]
+    fn fragmentation_scores() {
+        let mut image = TestImage::new(0x1000, 1);
+        image
+            .entry(0, b"FRAG    BIN", 0x20, 3, 2000)
+            .entry(1, b"FLAT    BIN", 0x20, 10, 1500)
+            .entry(2, b"ONE     BIN", 0x20, 20, 10)
+            .entry(3, b"EMPTY   BIN", 0x20, 0, 0)
+            .chain(&[3, 4, 8, 6])
+            .chain(&[10, 11, 12])
+            .chain(&[20]);
+        let mut fs = image.open("fragmentation");
+        let score = |fs: &mut FAT32, name| {
+            let file = file(fs, name);
+            fs.fragmentation_score(file).unwrap()
+        };
+        assert!((score(&mut fs, "FRAG.BIN") - 2.0 / 3.0).abs() < 1e-6);
+        assert_eq!(score(&mut fs, "FLAT.BIN"), 0.0);
+        assert_eq!(score(&mut fs, "ONE.BIN"), 0.0);
+        assert_eq!(score(&mut fs, "EMPTY.BIN"), 0.0);
+    }
+
+    #[test]
+    fn wipe_free_clusters() {
+        let mut image = TestImage::new(0x1000, 1);
+        image
+            .entry(0, b"KEPT    BIN", 0x20, 3, 512)
+            .chain(&[3])
+            .cluster(3, &content(512))
+            .cluster(4, &content(512))
+            .cluster(9, &content(512));
+        let mut fs = FAT32::open_rw(&image.path("wipe-free-space")).unwrap();
+        let free = fs.scan_free_clusters().unwrap();
+        let kept = fs.read_cluster(3).unwrap();
+
+        let mut calls = vec![];
+        let wiped = fs
+            .wipe_free_space_with_progress(|done, total| calls.push((done, total)))
+            .unwrap();
+        assert_eq!(wiped, free as u64 * 512);
+        assert_eq!(calls.len(), free as usize);
+        assert_eq!(calls.last(), Some(&(free, free)));
+
+        assert_eq!(fs.read_cluster(3).unwrap(), kept);
+        assert_eq!(fs.read_cluster(4).unwrap(), vec![0; 512]);
+        assert_eq!(fs.read_cluster(9).unwrap(), vec![0; 512]);
+        assert_eq!(fs.scan_free_clusters().unwrap(), free);
+    }
+
+    #[test]
+    fn secure_deletes() {
+        let mut image = TestImage::new(0x1000, 1);
+        image
+            .entry(0, b"SECRET  TXT", 0x20, 3, 700)
+            .entry(1, b"OTHER   TXT", 0x20, 5, 100)
+            .chain(&[3, 4])
+            .chain(&[5])
+            .cluster(3, &content(512))
+            .cluster(4, &content(512))
+            .cluster(5, &content(512));
+        let path = image.path("secure-delete");
+        let mut fs = FAT32::open_rw(&path).unwrap();
+        let other = fs.read_cluster(5).unwrap();
+        fs.secure_delete("SECRET.TXT", SecureDeleteOptions::default())
+            .unwrap();
+        assert!(matches!(
+            fs.open_path("SECRET.TXT"),
+            Err(FatError::NotFound)
+        ));
+        assert!(fs.free_space_map().unwrap().is_free(3));
+        assert!(fs.free_space_map().unwrap().is_free(4));
+        assert_eq!(fs.read_cluster(3).unwrap(), vec![0; 512]);
+        assert_eq!(fs.read_cluster(4).unwrap(), vec![0; 512]);
+        assert_eq!(fs.read_cluster(5).unwrap(), other);
+
+        // the last pass writes the pattern
+        let options = SecureDeleteOptions {
+            passes: 3,
+            pattern: 0x55,
+        };
+        fs.secure_delete("OTHER.TXT", options).unwrap();
+        assert_eq!(fs.read_cluster(5).unwrap(), vec![0x55; 512]);
+        assert!(matches!(
+            fs.secure_delete("OTHER.TXT", options),
+            Err(FatError::NotFound)
+        ));
+    }
+
+    #[test]
+    fn boot_sector_fields() {
+        let mut image = Vec::new();
+        FAT32::format(io::Cursor::new(&mut image), "FIELDS", 64 << 20).unwrap();
+        let fs = FAT32::from_bytes(&image).unwrap();
+        let boot = fs.boot_sector();
+        assert_eq!(boot.oem_name, "FAT-RS");
+        assert_eq!(boot.bytes_per_sector, 512);
+        assert_eq!(boot.reserved_sectors, 32);
+        assert_eq!(boot.num_fats, 2);
+        assert_eq!(boot.media, 0xf8);
+        assert_eq!(boot.total_sectors, 0x20000);
+        assert_eq!(boot.root_cluster, 2);
+        assert_eq!(boot.fs_info_sector, 1);
+        assert_eq!(boot.backup_boot_sector, 6);
+        assert_eq!(boot.drive_number, 0x80);
+        assert_eq!(&boot.volume_label, b"FIELDS     ");
+        assert_eq!(&boot.fs_type_label, b"FAT32   ");
+        assert_eq!(boot.volume_serial, fs.volume_serial_number());
+    }
+
+    #[test]
+    fn malformed_boot_sectors() {
+        // values found by fuzzing, which used to panic
+        let image = TestImage::new(0x1000, 2);
+        let mut data = image.data.clone();
+        data[36..40].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(
+            FAT32::from_bytes(&data),
+            Err(FatError::InvalidBootRecord("FATs too large"))
+        ));
+
+        for sectors_per_cluster in [0, 3, 6, 255] {
+            let mut data = image.data.clone();
+            data[13] = sectors_per_cluster;
+            assert!(matches!(
+                FAT32::from_bytes(&data),
+                Err(FatError::InvalidBootRecord(_))
+            ));
+        }
+
+        // clusters past 4 GiB
+        let mut data = image.data.clone();
+        data[13] = 128;
+        data[32..36].copy_from_slice(&u32::MAX.to_le_bytes());
+        let fs = FAT32::from_bytes(&data).unwrap();
+        assert_eq!(fs.volume_size(), u32::MAX);
+        let last = fs.total_cluster_count() + 1;
+        assert!(fs.cluster_offset(last).unwrap() > u32::MAX as u64);
+    }
+
+    #[test]
+    fn backup_boot_sectors() {
+        let mut image = Vec::new();
+        FAT32::format(io::Cursor::new(&mut image), "BACKUP", 64 << 20).unwrap();
+        let expected = FAT32::from_bytes(&image).unwrap().total_cluster_count();
+        // damaged label and sector size in the boot sector
+        image[11] = 3;
+        image[71..82].copy_from_slice(b"BROKEN     ");
+        assert!(FAT32::from_bytes(&image).is_err());
+        image[11] = 0;
+        image[12] = 2;
+        // and a geometry that still opens
+        image[13] *= 2;
+        image[14] += 8;
+
+        let mut fs = FAT32::from_reader_rw(io::Cursor::new(&mut image)).unwrap();
+        assert_ne!(fs.total_cluster_count(), expected);
+        let backup = fs.backup_boot_sector().unwrap();
+        assert_eq!(&backup.volume_label, b"BACKUP     ");
+        assert_eq!(backup.bytes_per_sector, 512);
+        assert_eq!(backup.backup_boot_sector, 6);
+        assert_eq!(&backup.fs_type_label, b"FAT32   ");
+        fs.restore_boot_sector_from_backup().unwrap();
+        // seen by the open handle
+        assert_eq!(fs.volume_name(), "BACKUP");
+        assert_eq!(*fs.boot_sector(), backup);
+        assert_eq!(fs.total_cluster_count(), expected);
+        let root = fs.root_directory();
+        fs.write_file(root, "A.TXT", b"restored").unwrap();
+        assert_eq!(fs.check_integrity().unwrap(), vec![]);
+        fs.flush().unwrap();
+        drop(fs);
+        let mut fs = FAT32::from_bytes(&image).unwrap();
+        assert_eq!(fs.volume_name(), "BACKUP");
+        assert_eq!(fs.stat("/A.TXT").unwrap().size, 8);
+
+        // no backup in the test images
+        let mut fs = TestImage::new(0x1000, 1).open("no-backup");
+        assert!(matches!(
+            fs.backup_boot_sector(),
+            Err(FatError::InvalidBootRecord(_))
+        ));
+    }
+
+    #[test]
+    fn case_insensitive_names() {
+        assert!(fat_name_eq("readme.txt", "README.TXT"));
+        assert!(fat_name_eq("Ünïcode.txt", "Ünïcode.TXT"));
+        assert!(!fat_name_eq("Ünïcode.txt", "üNÏCODE.TXT"));
+        assert!(!fat_name_eq("README.TXT", "README.TX"));
+
+        // lowercase short name written by another tool
+        let mut image = TestImage::new(0x1000, 1);
+        image
+            .entry(0, b"readme  txt", 0x20, 0, 0)
+            .entry(1, b"DIR        ", 0x10, 3, 0)
+            .chain(&[3]);
+        let path = image.path("case");
+        let mut fs = FAT32::open_rw(&path).unwrap();
+        let root = fs.root_directory();
+        assert!(fs.find_entry(root, "README.TXT").unwrap().is_some());
+        assert!(matches!(fs.open_path("/dir/"), Ok(EntryType::Dir(_))));
+        assert!(matches!(
+            fs.write_file(root, "Readme.txt", b"data"),
+            Err(FatError::AlreadyExists)
+        ));
+    }
+
+    #[test]
+    fn short_name_checks() {
+        assert!(validate_83_name("README", "TXT").is_ok());
+        assert!(validate_83_name("MAKEFILE", "").is_ok());
+        for (name, ext) in [
+            ("", "TXT"),
+            ("TOOLONGNAME", "TXT"),
+            ("README", "TEXT"),
+            ("readme", "TXT"),
+            ("READ ME", "TXT"),
+            ("A+B", "C"),
+            ("ÉTÉ", ""),
+        ] {
+            assert!(matches!(
+                validate_83_name(name, ext),
+                Err(FatError::InvalidName)
+            ));
+        }
+
+        let short = |long: &str| to_83_name(long).map(|(name, ext)| [&name[..], &ext[..]].concat());
+        assert_eq!(short("readme.txt").unwrap(), b"README  TXT");
+        assert_eq!(short("a long file name.text").unwrap(), b"ALONGFILTEX");
+        assert_eq!(short("archive.tar.gz").unwrap(), b"ARCHIVETGZ ");
+        assert_eq!(short(".profile").unwrap(), b"PROFILE    ");
+        assert_eq!(short("été[1].c").unwrap(), b"T1      C  ");
+        assert_eq!(short("+++"), None);
+        assert_eq!(short("..."), None);
+
+        let mut fs = FAT32::open_rw(&TestImage::new(0x1000, 1).path("short-names")).unwrap();
+        let root = fs.root_directory();
+        assert!(matches!(
+            fs.write_file(root, "a*b.txt", b"data"),
+            Err(FatError::InvalidName)
+        ));
+        assert!(matches!(
+            fs.create_directory(root, "long directory"),
+            Err(FatError::InvalidName)
+        ));
+    }
+
+    #[test]
+    fn raw_entries() {
+        let mut raw = [0u8; 32];
+        raw[..11].copy_from_slice(b"README  TXT");
+        raw[11] = 0x21;
+        raw[12] = 0x18;
+        raw[13] = 150;
+        raw[14..26].copy_from_slice(&[1, 2, 3, 4, 5, 6, 0x01, 0, 7, 8, 9, 10]);
+        raw[26..28].copy_from_slice(&[0x34, 0x12]);
+        raw[28..].copy_from_slice(&1234u32.to_le_bytes());
+        let entry = DirectoryEntry::from_bytes(raw).unwrap();
+        assert_eq!(entry.full_name(), "README.TXT");
+        assert_eq!(entry.cluster, 0x11234);
+        assert_eq!(entry.size(), 1234);
+        assert_eq!(entry.raw_bytes(), raw);
+
+        // 0xe5 as first character, and deleted entries
+        raw[0] = 0x05;
+        assert_eq!(DirectoryEntry::from_bytes(raw).unwrap().raw_bytes(), raw);
+        raw[0] = 0xe5;
+        let deleted = DirectoryEntry::from_bytes(raw).unwrap();
+        assert_eq!(deleted.full_name(), "?EADME.TXT");
+        assert_eq!(deleted.raw_bytes(), raw);
+
+        raw[0] = 0;
+        assert!(DirectoryEntry::from_bytes(raw).is_none());
+        raw[0] = b'A';
+        raw[11] = 0xf;
+        assert!(DirectoryEntry::from_bytes(raw).is_none());
+    }
+
+    #[test]
+    fn clean_listings() {
+        let mut fs = FAT32::open_rw(&TestImage::new(0x1000, 1).path("clean")).unwrap();
+        let root = fs.root_directory();
+        let dir = fs.create_directory(root, "DIR").unwrap();
+        fs.write_file(dir, "A.TXT", b"a").unwrap();
+        fs.set_volume_label("CLEAN").unwrap();
+
+        let names = |entries: Vec<DirectoryEntry>| -> Vec<String> {
+            entries.iter().map(|entry| entry.full_name()).collect()
+        };
+        let entries = fs.read_directory(dir).unwrap();
+        assert!(entries[0].is_dot_entry() && entries[1].is_dot_entry());
+        assert!(!entries[2].is_dot_entry());
+        assert_eq!(names(entries), [".", "..", "A.TXT"]);
+        assert_eq!(names(fs.read_directory_clean(dir).unwrap()), ["A.TXT"]);
+        assert_eq!(names(fs.read_directory_clean(root).unwrap()), ["DIR"]);
+    }
+
+    #[test]
+    fn path_checks() {
+        let mut fs = FAT32::open_rw(&TestImage::new(0x1000, 1).path("exists")).unwrap();
+        let root = fs.root_directory();
+        let dir = fs.create_directory(root, "DIR").unwrap();
+        fs.write_file(dir, "A.TXT", b"a").unwrap();
+
+        assert!(fs.path_exists("/").unwrap());
+        assert!(fs.path_exists("/dir/a.txt").unwrap());
+        assert!(!fs.path_exists("/dir/b.txt").unwrap());
+        assert!(!fs.path_exists("/missing/a.txt").unwrap());
+        assert!(fs.is_dir("/DIR").unwrap());
+        assert!(!fs.is_dir("/DIR/A.TXT").unwrap());
+        assert!(fs.is_file("/DIR/A.TXT").unwrap());
+        assert!(!fs.is_file("/DIR").unwrap());
+        assert!(!fs.is_file("/NONE").unwrap());
+        assert!(matches!(
+            fs.path_exists("/DIR/A.TXT/B"),
+            Err(FatError::NotADirectory)
+        ));
+    }
+
+    #[test]
+    fn sorted_listings() {
+        let mut fs = FAT32::open_rw(&TestImage::new(0x1000, 1).path("sorted")).unwrap();
+        let root = fs.root_directory();
+        let dir = fs.create_directory(root, "DIR").unwrap();
+        for (name, size) in [("beta.txt", 30), ("ALPHA.TXT", 20), ("gamma.txt", 10)] {
+            fs.write_file(dir, name, &content(size)).unwrap();
+        }
+        fs.write_file(dir, "DELTA.TXT", &content(20)).unwrap();
+        let mut sorted = |key| -> Vec<String> {
+            fs.read_directory_sorted(dir, key)
+                .unwrap()
+                .iter()
+                .map(|entry| entry.full_name())
+                .collect()
+        };
+        assert_eq!(
+            sorted(SortKey::Name),
+            [".", "..", "ALPHA.TXT", "BETA.TXT", "DELTA.TXT", "GAMMA.TXT"]
+        );
+        assert_eq!(
+            sorted(SortKey::Size),
+            [".", "..", "GAMMA.TXT", "ALPHA.TXT", "DELTA.TXT", "BETA.TXT"]
+        );
+        assert_eq!(
+            sorted(SortKey::SizeDesc),
+            [".", "..", "BETA.TXT", "ALPHA.TXT", "DELTA.TXT", "GAMMA.TXT"]
+        );
+    }
+
+    #[test]
+    fn volume_infos() {
+        let mut fs = TestImage::new(0x1000, 1).fs_info(1000).open("volume-info");
+        let info = fs.volume_info().unwrap();
+        assert_eq!(info.label, "TEST");
+        assert_eq!(info.serial, 0x1234abcd);
+        assert_eq!(info.fat_type, FatType::Fat32);
+        assert_eq!(info.oem_name, "FAT-RS");
+        assert_eq!((info.sector_size, info.cluster_size_sectors), (512, 1));
+        assert_eq!(info.total_sectors, 0x1000);
+        assert_eq!(info.total_clusters, fs.total_cluster_count());
+        assert_eq!(info.free_clusters, 1000);
+        assert_eq!(info.total_bytes, 0x1000 * 512);
+        assert_eq!(info.free_bytes, 1000 * 512);
+    }
+
+    // proptest needs getrandom, not built for wasm32
+    #[cfg(not(target_arch = "wasm32"))]
+    proptest::proptest! {
+        #[test]
+        fn raw_entry_round_trips(raw in proptest::array::uniform32(proptest::num::u8::ANY)) {
+            // every regular entry, deleted or not, is stored back as is
+            if let Some(entry) = DirectoryEntry::from_bytes(raw) {
+                proptest::prop_assert_eq!(entry.raw_bytes(), raw);
+            }
+        }
+
+        #[test]
+        fn short_name_round_trips(name in "[A-Z0-9]{1,8}", ext in "[A-Z0-9]{0,3}") {
+            validate_83_name(&name, &ext).unwrap();
+            let full = if ext.is_empty() { name.clone() } else { format!("{}.{}", name, ext) };
+            let mut raw = [0u8; 32];
+            raw[..11].copy_from_slice(&short_name(&full).unwrap());
+            raw[11] = 0x20;
+            let entry = DirectoryEntry::from_bytes(raw).unwrap();
+            proptest::prop_assert_eq!(entry.full_name(), full);
+        }
+    }
+
+    #[test]
+    fn defragment_files() {
+        let data = content(1500);
+        let mut image = TestImage::new(0x1000, 1);
+        image
+            .entry(0, b"FRAG    BIN", 0x20, 3, 1500)
+            .entry(1, b"DONE    BIN", 0x20, 5, 10)
+            .chain(&[3, 6, 4])
+            .chain(&[5])
+            .cluster(3, &data[..512])
+            .cluster(6, &data[512..1024])
+            .cluster(4, &data[1024..]);
+        let path = image.path("defrag");
+        let mut fs = FAT32::open_rw(&path).unwrap();
+
+        let report = fs.defragment_file("/FRAG.BIN").unwrap();
+        assert_eq!(
+            report,
+            DefragReport {
+                moved: true,
+                old_first: 3,
+                new_first: 7,
+                clusters_moved: 3
+            }
+        );
+        assert_eq!(fs.chain_to_vec(7).unwrap(), [7, 8, 9]);
+        let file = file(&mut fs, "FRAG.BIN");
+        assert_eq!(fs.read_file(file).unwrap(), data);
+        assert_eq!(fs.check_integrity().unwrap(), vec![]);
+        for cluster in [3, 4, 6].iter() {
+            assert_eq!(fs.fat_lookup(*cluster).unwrap(), 0);
+        }
+
+        assert!(!fs.defragment_file("/DONE.BIN").unwrap().moved);
+        assert!(matches!(
+            fs.defragment_file("/"),
+            Err(FatError::IsDirectory)
+        ));
+    }
+
+    #[test]
+    fn raw_sectors() {
+        let mut fs =
+            FAT32::open_rw(&TestImage::new(0x1000, 1).fs_info(100).path("sectors")).unwrap();
+        let boot = fs.read_sector(0).unwrap();
+        assert_eq!(&boot[71..75], b"TEST");
+
+        // FSInfo through the sector primitives
+        let mut sector = fs.read_sector(1).unwrap();
+        LittleEndian::write_u32(&mut sector[488..], 42);
+        fs.write_sector(1, &sector).unwrap();
+        assert_eq!(fs.read_fsinfo().unwrap().free_clusters, 42);
+
+        assert!(matches!(
+            fs.read_sector(0x1000),
+            Err(FatError::InvalidSector { max: 0xfff, .. })
+        ));
+        assert!(matches!(
+            fs.write_sector(1, &sector[..12]),
+            Err(FatError::InvalidOperation)
+        ));
+    }
+
+    #[test]
+    fn fat_cache() {
+        let data = content(2000);
+        let path = TestImage::new(0x1000, 2).path("cache");
+        let mut fs = FAT32::open_rw(&path).unwrap();
+        let root = fs.root_directory();
+        let written = fs.write_file(root, "DATA.BIN", &data).unwrap();
+
+        // the whole chain is in one FAT sector
+        let (hits, misses) = fs.cache_stats();
+        assert_eq!(fs.read_file(written).unwrap(), data);
+        let (new_hits, new_misses) = fs.cache_stats();
+        assert!(new_hits - hits >= 3);
+        assert!(new_misses - misses <= 1);
+
+        // writes are seen by the following reads
+        fs.truncate_file(root, "DATA.BIN", 100).unwrap();
+        assert_eq!(fs.chain_to_vec(written.cluster).unwrap().len(), 1);
+        fs.append_to_file(root, "DATA.BIN", &data).unwrap();
+        assert_eq!(fs.chain_to_vec(written.cluster).unwrap().len(), 5);
+
+        fs.flush().unwrap();
+        let mut fs = FAT32::with_cache_size(&path, 0).unwrap();
+        let file = file(&mut fs, "DATA.BIN");
+        fs.read_file(file).unwrap();
+        assert_eq!(fs.cache_stats().0, 0);
+    }
+
+    #[test]
+    fn dirty_sectors() {
+        let data = content(700);
+        let image = TestImage::new(0x1000, 1);
+        let path = image.path("dirty");
+        let mut fs = FAT32::open_rw(&path).unwrap();
+        let root = fs.root_directory();
+        let written = fs.write_file(root, "DATA.BIN", &data).unwrap();
+
+        // staged in memory, already seen by reads
+        assert_eq!(fs::read(&path).unwrap(), image.data);
+        assert_eq!(fs.read_file(written).unwrap(), data);
+        fs.flush().unwrap();
+        let mut other = FAT32::new(&path).unwrap();
+        assert_eq!(other.read_file(written).unwrap(), data);
+
+        // direct writes skip the buffer, and replace
+        // a staged version of the sector
+        let sector = fs.cluster_start(written.cluster).unwrap();
+        fs.write_sector(sector, &[1u8; SECTOR_SIZE as usize])
+            .unwrap();
+        fs.write_sector_direct(sector, &[2u8; SECTOR_SIZE as usize])
+            .unwrap();
+        assert_eq!(
+            other.read_sector(sector).unwrap(),
+            [2u8; SECTOR_SIZE as usize]
+        );
+        assert_eq!(fs.read_sector(sector).unwrap(), [2u8; SECTOR_SIZE as usize]);
+
+        // written back when dropped
+        fs.write_sector(sector, &[3u8; SECTOR_SIZE as usize])
+            .unwrap();
+        drop(fs);
+        assert_eq!(
+            other.read_sector(sector).unwrap(),
+            [3u8; SECTOR_SIZE as usize]
+        );
+    }
+
+    #[test]
+    fn read_deleted_entries() {
+        let mut fs = FAT32::open_rw(&TestImage::new(0x1000, 1).path("deleted")).unwrap();
+        let root = fs.root_directory();
+        fs.write_file(root, "KEEP.TXT", &content(10)).unwrap();
+        let file = fs.write_file(root, "GONE.TXT", &content(700)).unwrap();
+        fs.delete_file(root, "GONE.TXT").unwrap();
+
+        assert_eq!(fs.read_directory(root).unwrap().len(), 1);
+        let entries = fs.read_directory_with_deleted(root).unwrap();
+        let entries: Vec<(bool, String, u32, u32)> = entries
+            .iter()
+            .map(|(deleted, entry)| (*deleted, entry.full_name(), entry.cluster, entry.size))
+            .collect();
+        assert_eq!(
+            entries,
+            [
+                (false, String::from("KEEP.TXT"), 3, 10),
+                (true, String::from("?ONE.TXT"), file.cluster, 700)
+            ]
+        );
+    }
+
+    #[test]
+    fn escaped_e5_names() {
+        let mut image = TestImage::new(0x1000, 1);
+        image
+            .entry(0, b"\x05ABC    TXT", 0x20, 0, 0)
+            .entry(1, b"\xe5GONE   TXT", 0x20, 0, 0);
+        let mut fs = image.open("e5-names");
+        let root = fs.root_directory();
+        let entries = fs.read_directory(root).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].name, *b"\xe5ABC    TXT");
+        assert_eq!(entries[0].full_name(), "\u{fffd}ABC.TXT");
+    }
+
+    #[test]
+    fn recover_files() {
+        let data = content(1300);
+        let path = TestImage::new(0x1000, 1).fs_info(100).path("recover");
+        let mut fs = FAT32::open_rw(&path).unwrap();
+        let root = fs.root_directory();
+        let dir = fs.create_directory(root, "DIR").unwrap();
+        fs.write_file(root, "DATA.BIN", &data).unwrap();
+        fs.write_file(root, "EMPTY.BIN", &[]).unwrap();
+        fs.delete_file(root, "DATA.BIN").unwrap();
+        fs.delete_file(root, "EMPTY.BIN").unwrap();
+
+        let deleted = |fs: &mut FAT32<fs::File, ReadWrite>, name: &str| {
+            fs.read_directory_with_deleted(root)
+                .unwrap()
+                .into_iter()
+                .find(|(deleted, entry)| *deleted && entry.full_name() == name)
+                .unwrap()
+                .1
+        };
+        let entry = deleted(&mut fs, "?ATA.BIN");
+        let file = fs.recover_file(entry, "DATA.BIN", dir).unwrap();
+        assert_eq!(fs.read_file(file).unwrap(), data);
+        assert_eq!(fs.read_fsinfo().unwrap().free_clusters, 100 - 1 - 3);
+        assert!(fs.stat("/DIR/DATA.BIN").is_ok());
+
+        let entry = deleted(&mut fs, "?MPTY.BIN");
+        fs.recover_file(entry, "EMPTY.BIN", root).unwrap();
+        assert_eq!(fs.stat("/EMPTY.BIN").unwrap().size, 0);
+
+        // clusters allocated again after the deletion
+        fs.delete_file(dir, "DATA.BIN").unwrap();
+        fs.write_file(root, "OTHER.BIN", &data[..100]).unwrap();
+        let entry = fs
+            .read_directory_with_deleted(dir)
+            .unwrap()
+            .into_iter()
+            .find(|(deleted, _)| *deleted)
+            .unwrap()
+            .1;
+        assert!(matches!(
+            fs.recover_file(entry, "DATA.BIN", root),
+            Err(FatError::ClusterOverwritten(_))
+        ));
+    }
+
+    #[test]
+    fn orphan_clusters() {
+        let path = TestImage::new(0x1000, 1)
+            .entry(0, b"DATA    BIN", 0x20, 3, 1000)
+            .chain(&[3, 4])
+            // lost chains, one looping on itself
+            .chain(&[10, 12, 11])
+            .chain(&[20])
+            .fat_entry(0, 30, 31)
+            .fat_entry(0, 31, 30)
+            .path("orphans");
+        let mut fs = FAT32::open_rw(&path).unwrap();
+        assert_eq!(fs.find_orphan_clusters().unwrap(), [10, 20, 30]);
+
+        let root = fs.root_directory();
+        fs.create_directory(root, "DIR").unwrap();
+        assert_eq!(fs.find_orphan_clusters().unwrap(), [10, 20, 30]);
+    }
+
+    #[test]
+    fn copy_files() {
+        let data = content(1300);
+        let mut fs = FAT32::open_rw(&TestImage::new(0x1000, 1).path("copy")).unwrap();
+        let root = fs.root_directory();
+        let dir = fs.create_directory(root, "DIR").unwrap();
+        fs.write_file(dir, "DATA.BIN", &data).unwrap();
+
+        fs.copy_file("/DIR/DATA.BIN", "/COPY.BIN").unwrap();
+        fs.copy_file("DIR/DATA.BIN", "/DIR/COPY.BIN").unwrap();
+        for path in ["/COPY.BIN", "/DIR/COPY.BIN"] {
+            match fs.open_path(path).unwrap() {
+                EntryType::File(file) => assert_eq!(fs.read_file(file).unwrap(), data),
+                EntryType::Dir(_) => panic!("{} is a file", path),
+            }
+        }
+        assert_ne!(
+            fs.stat("/COPY.BIN").unwrap().first_cluster,
+            fs.stat("/DIR/DATA.BIN").unwrap().first_cluster
+        );
+
+        assert!(matches!(
+            fs.copy_file("/DIR/DATA.BIN", "/COPY.BIN"),
+            Err(FatError::AlreadyExists)
+        ));
+        assert!(matches!(
+            fs.copy_file("/NONE.BIN", "/OTHER.BIN"),
+            Err(FatError::NotFound)
+        ));
+        assert!(matches!(
+            fs.copy_file("/COPY.BIN", "/NONE/OTHER.BIN"),
+            Err(FatError::NotFound)
+        ));
+        assert!(matches!(
+            fs.copy_file("/DIR", "/OTHER"),
+            Err(FatError::IsDirectory)
+        ));
+    }
+
+    #[test]
+    fn move_files() {
+        let data = content(1300);
+        let path = TestImage::new(0x1000, 1).fs_info(100).path("move");
+        let mut fs = FAT32::open_rw(&path).unwrap();
+        let root = fs.root_directory();
+        let dir = fs.create_directory(root, "DIR").unwrap();
+        let file = fs.write_file(root, "DATA.BIN", &data).unwrap();
+        let free = fs.read_fsinfo().unwrap().free_clusters;
+
+        fs.move_file("/DATA.BIN", "/DIR/MOVED.BIN").unwrap();
+        assert!(matches!(fs.stat("/DATA.BIN"), Err(FatError::NotFound)));
+        let stat = fs.stat("/DIR/MOVED.BIN").unwrap();
+        assert_eq!((stat.first_cluster, stat.size), (file.cluster, 1300));
+        assert_eq!(fs.read_file(file).unwrap(), data);
+        assert_eq!(fs.read_fsinfo().unwrap().free_clusters, free);
+
+        // same directory: renamed in place
+        fs.move_file("/DIR/MOVED.BIN", "/DIR/SAME.BIN").unwrap();
+        assert_eq!(fs.read_directory(dir).unwrap().len(), 3);
+        assert_eq!(
+            fs.stat("/DIR/SAME.BIN").unwrap().first_cluster,
+            file.cluster
+        );
+
+        fs.write_file(root, "OTHER.BIN", &[]).unwrap();
+        assert!(matches!(
+            fs.move_file("/OTHER.BIN", "/DIR/SAME.BIN"),
+            Err(FatError::AlreadyExists)
+        ));
+        assert!(matches!(
+            fs.move_file("/DIR", "/NEW"),
+            Err(FatError::IsDirectory)
+        ));
+        assert_eq!(fs.check_integrity().unwrap(), vec![]);
+    }
+
+    #[test]
+    fn stream_writes() {
+        let data = content(3000);
+        let path = TestImage::new(0x1000, 1).fs_info(100).path("writer");
+        let mut fs = FAT32::open_rw(&path).unwrap();
+        let root = fs.root_directory();
+
+        let mut writer = fs.create_file(root, "DATA.BIN").unwrap();
+        for chunk in data.chunks(700) {
+            writer.write_all(chunk).unwrap();
+        }
+        writer.flush().unwrap();
+        drop(writer);
+        let file = file(&mut fs, "DATA.BIN");
+        assert_eq!(fs.read_file(file).unwrap(), data);
+        assert_eq!(fs.read_fsinfo().unwrap().free_clusters, 100 - 6);
+
+        // size written back when dropped
+        let mut writer = fs.create_file(root, "SHORT.BIN").unwrap();
+        writer.write_all(&data[..100]).unwrap();
+        drop(writer);
+        let mut fs = FAT32::new(&path).unwrap();
+        assert_eq!(fs.stat("/SHORT.BIN").unwrap().size, 100);
+        assert_eq!(fs.check_integrity().unwrap(), vec![]);
+    }
+
+    #[test]
+    fn cyclic_chain() {
+        // 3 -> 4 -> 3 -> ...
+        let mut fs = TestImage::new(0x1000, 1)
+            .entry(0, b"LOOP    BIN", 0x20, 3, 4096)
+            .entry(1, b"LOOP       ", 0x10, 3, 0)
+            .fat_entry(0, 3, 4)
+            .fat_entry(0, 4, 3)
+            // deleted entries, no end marker
+            .cluster(3, &[0xe5; 512])
+            .cluster(4, &[0xe5; 512])
+            .open("cycle");
+
+        let file = file(&mut fs, "LOOP.BIN");
+        assert!(matches!(
+            fs.read_file(file),
+            Err(FatError::CyclicFatChain(3))
+        ));
+        assert!(matches!(
+            fs.cluster_chain_length(3),
+            Err(FatError::CyclicFatChain(3))
+        ));
+        let dir = Directory { cluster: 3 };
+        assert!(matches!(
+            fs.read_directory(dir),
+            Err(FatError::CyclicFatChain(3))
+        ));
     }
 }
@@ -0,0 +1,329 @@
+use crate::error::FatError;
+use crate::fat16;
+use crate::fat32::{self, FAT32};
+#[cfg(feature = "std")]
+use crate::io;
+use crate::io::{Read, Seek};
+use alloc::{string::String, vec::Vec};
+#[cfg(feature = "std")]
+use std::{fs, path};
+
+// operations available on every kind of FAT volume,
+// for callers that don't know which one they have
+pub trait FatFilesystem {
+    fn root_directory(&self) -> Directory;
+    fn read_directory(&mut self, dir: Directory) -> Result<Vec<DirectoryEntry>, FatError>;
+    fn volume_name(&self) -> &str;
+    fn sector_count(&self) -> u32;
+    fn volume_size(&self) -> u32;
+    fn free_space(&mut self) -> Result<u64, FatError>;
+}
+
+// FAT variant of a volume
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum FatType {
+    Fat12,
+    Fat16,
+    Fat32,
+}
+
+impl From<fat16::FatType> for FatType {
+    fn from(fat_type: fat16::FatType) -> FatType {
+        match fat_type {
+            fat16::FatType::Fat12 => FatType::Fat12,
+            fat16::FatType::Fat16 => FatType::Fat16,
+        }
+    }
+}
+
+// metadata of a volume, as returned by volume_info
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct VolumeInfo {
+    pub label: String,
+    pub serial: u32,
+    pub fat_type: FatType,
+    pub oem_name: String,
+    pub sector_size: u32,
+    pub cluster_size_sectors: u32,
+    pub total_sectors: u32,
+    pub total_clusters: u32,
+    pub free_clusters: u32,
+    pub total_bytes: u64,
+    pub free_bytes: u64,
+}
+
+// directory of a FAT12/FAT16 or FAT32 volume
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Directory {
+    Fat16(fat16::Directory),
+    Fat32(fat32::Directory),
+}
+
+// directory entry of a FAT12/FAT16 or FAT32 volume
+#[derive(Clone, Debug)]
+pub enum DirectoryEntry {
+    Fat16(fat16::DirectoryEntry),
+    Fat32(fat32::DirectoryEntry),
+}
+
+impl DirectoryEntry {
+    pub fn full_name(&self) -> String {
+        match self {
+            DirectoryEntry::Fat16(entry) => entry.full_name(),
+            DirectoryEntry::Fat32(entry) => entry.full_name(),
+        }
+    }
+
+    pub fn size(&self) -> u32 {
+        match self {
+            DirectoryEntry::Fat16(entry) => entry.size(),
+            DirectoryEntry::Fat32(entry) => entry.size(),
+        }
+    }
+
+    pub fn is_directory(&self) -> bool {
+        match self {
+            DirectoryEntry::Fat16(entry) => entry.is_directory(),
+            DirectoryEntry::Fat32(entry) => entry.is_directory(),
+        }
+    }
+
+    pub fn directory(&self) -> Option<Directory> {
+        // the directory to read for a directory entry
+        match self {
+            DirectoryEntry::Fat16(entry) => match entry.entry_type() {
+                fat16::EntryType::Dir(dir) => Some(Directory::Fat16(dir)),
+                fat16::EntryType::File(_) => None,
+            },
+            DirectoryEntry::Fat32(entry) => match entry.entry_type() {
+                fat32::EntryType::Dir(dir) => Some(Directory::Fat32(dir)),
+                fat32::EntryType::File(_) => None,
+            },
+        }
+    }
+}
+
+impl<B: Read + Seek, M> FatFilesystem for FAT32<B, M> {
+    fn root_directory(&self) -> Directory {
+        Directory::Fat32(FAT32::root_directory(self))
+    }
+
+    fn read_directory(&mut self, dir: Directory) -> Result<Vec<DirectoryEntry>, FatError> {
+        match dir {
+            Directory::Fat32(dir) => Ok(FAT32::read_directory(self, dir)?
+                .into_iter()
+                .map(DirectoryEntry::Fat32)
+                .collect()),
+            Directory::Fat16(_) => Err(FatError::InvalidOperation),
+        }
+    }
+
+    fn volume_name(&self) -> &str {
+        FAT32::volume_name(self)
+    }
+
+    fn sector_count(&self) -> u32 {
+        FAT32::sector_count(self)
+    }
+
+    fn volume_size(&self) -> u32 {
+        FAT32::volume_size(self)
+    }
+
+    fn free_space(&mut self) -> Result<u64, FatError> {
+        FAT32::free_space(self)
+    }
+}
+
+impl<B: Read + Seek> FatFilesystem for fat16::FileSystem<B> {
+    fn root_directory(&self) -> Directory {
+        Directory::Fat16(fat16::FileSystem::root_directory(self))
+    }
+
+    fn read_directory(&mut self, dir: Directory) -> Result<Vec<DirectoryEntry>, FatError> {
+        match dir {
+            Directory::Fat16(dir) => Ok(fat16::FileSystem::read_directory(self, dir)?
+                .into_iter()
+                .map(DirectoryEntry::Fat16)
+                .collect()),
+            Directory::Fat32(_) => Err(FatError::InvalidOperation),
+        }
+    }
+
+    fn volume_name(&self) -> &str {
+        fat16::FileSystem::volume_name(self)
+    }
+
+    fn sector_count(&self) -> u32 {
+        self.sectors_count()
+    }
+
+    fn volume_size(&self) -> u32 {
+        fat16::FileSystem::volume_size(self)
+    }
+
+    fn free_space(&mut self) -> Result<u64, FatError> {
+        fat16::FileSystem::free_space(self)
+    }
+}
+
+#[cfg(feature = "std")]
+pub fn open(path: &path::Path) -> Result<Box<dyn FatFilesystem>, FatError> {
+    // FAT32 from 65525 clusters, as in the FAT specification, or
+    // when the boot record has the FAT32 layout (small volumes
+    // formatted as FAT32 have fewer clusters); FAT12 and FAT16
+    // are told apart by the fat16 module
+    let mut file = fs::File::open(path)?;
+    let (clusters, fat32_layout) = cluster_count(&mut file)?;
+    if fat32_layout || clusters >= 65525 {
+        Ok(Box::new(FAT32::from_reader(file)?))
+    } else {
+        Ok(Box::new(fat16::FileSystem::from_reader(file)?))
+    }
+}
+
+#[cfg(feature = "std")]
+fn cluster_count<R: Read + Seek>(file: &mut R) -> Result<(u32, bool), FatError> {
+    // number of clusters in the data region, from the
+    // boot record fields common to every FAT type, and
+    // whether the 16 bits FAT size is 0 as in FAT32
+    use byteorder::{ByteOrder, LittleEndian};
+
+    let mut data = [0u8; 40];
+    file.seek(io::SeekFrom::Start(0))?;
+    file.read_exact(&mut data)?;
+    let sector_size = LittleEndian::read_u16(&data[11..]) as u32;
+    let cluster_size = data[13] as u32;
+    let reserved_sectors = LittleEndian::read_u16(&data[14..]) as u32;
+    let fat_count = data[16] as u32;
+    let root_entries = LittleEndian::read_u16(&data[17..]) as u32;
+    let small_sector_count = LittleEndian::read_u16(&data[19..]) as u32;
+    // skip media parameter type
+    let small_fat_size = LittleEndian::read_u16(&data[22..]) as u32;
+    if sector_size == 0 || cluster_size == 0 {
+        return Err(FatError::InvalidBootRecord("null sector or cluster size"));
+    }
+
+    // the 32 bits fields are only used when
+    // the 16 bits ones are 0
+    let large_sector_count = LittleEndian::read_u32(&data[32..]);
+    let large_fat_size = LittleEndian::read_u32(&data[36..]);
+    let sector_count = match small_sector_count {
+        0 => large_sector_count,
+        count => count,
+    };
+    let fat_size = match small_fat_size {
+        0 => large_fat_size,
+        size => size,
+    };
+
+    let root_sectors = (root_entries * 32).div_ceil(sector_size);
+    let data_start = fat_count
+        .checked_mul(fat_size)
+        .and_then(|fats| fats.checked_add(reserved_sectors + root_sectors))
+        .ok_or(FatError::InvalidBootRecord("FATs too large"))?;
+    let clusters = sector_count.saturating_sub(data_start) / cluster_size;
+    Ok((clusters, small_fat_size == 0))
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use byteorder::{ByteOrder, LittleEndian};
+
+    #[test]
+    fn open_any_volume() {
+        // FAT32, 128 MiB
+        let path = std::env::temp_dir().join("fat-rs-open-fat32.img");
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .unwrap();
+        let mut formatted = FAT32::format(file, "BIG", 128 << 20).unwrap();
+        let root = formatted.root_directory();
+        formatted.create_directory(root, "DIR").unwrap();
+        formatted.flush().unwrap();
+
+        let mut fs = open(&path).unwrap();
+        assert_eq!(fs.volume_name(), "BIG");
+        assert_eq!(fs.volume_size(), 128 << 20);
+        let root = fs.root_directory();
+        let entries = fs.read_directory(root).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].full_name(), "DIR");
+        let dir = entries[0].directory().unwrap();
+        assert_eq!(fs.read_directory(dir).unwrap().len(), 2);
+
+        // FAT12, 50 KiB: 1 reserved sector, 2 FATs
+        // of 1 sector and 1 sector of root entries
+        let mut data = vec![0u8; 100 * 512];
+        LittleEndian::write_u16(&mut data[11..], 512);
+        data[13] = 1;
+        LittleEndian::write_u16(&mut data[14..], 1);
+        data[16] = 2;
+        LittleEndian::write_u16(&mut data[17..], 16);
+        LittleEndian::write_u16(&mut data[19..], 100);
+        LittleEndian::write_u16(&mut data[22..], 1);
+        data[38] = 0x29;
+        data[43..54].copy_from_slice(b"FLOPPY     ");
+        // reserved FAT entries 0 and 1
+        data[512..515].copy_from_slice(&[0xf8, 0xff, 0xff]);
+        let path = std::env::temp_dir().join("fat-rs-open-fat12.img");
+        fs::write(&path, &data).unwrap();
+
+        let mut fs = open(&path).unwrap();
+        assert_eq!(fs.volume_name(), "FLOPPY");
+        assert!(matches!(
+            cluster_count(&mut io::Cursor::new(&data)),
+            Ok((96, false))
+        ));
+        assert_eq!(fs.sector_count(), 100);
+        assert_eq!(fs.free_space().unwrap(), 96 * 512);
+        let root = fs.root_directory();
+        assert!(fs.read_directory(root).unwrap().is_empty());
+    }
+
+    #[test]
+    fn open_small_fat32() {
+        let mut image = crate::builder::ImageBuilder::new(33 << 20)
+            .label("SMALL")
+            .add_file("/a.txt", b"a")
+            .build()
+            .unwrap();
+        let path = std::env::temp_dir().join("fat-rs-open-small.img");
+        fs::write(&path, &image).unwrap();
+        let mut fs = open(&path).unwrap();
+        assert_eq!(fs.volume_name(), "SMALL");
+        let root = fs.root_directory();
+        let entries = fs.read_directory(root).unwrap();
+        assert_eq!(entries[0].full_name(), "A.TXT");
+
+        // cut down to far fewer than 65525 clusters,
+        // as other tools can format, still FAT32
+        image.truncate(10000 * 512);
+        LittleEndian::write_u32(&mut image[32..], 10000);
+        fs::write(&path, &image).unwrap();
+        assert!(matches!(
+            cluster_count(&mut io::Cursor::new(&image)),
+            Ok((8920, true))
+        ));
+        let mut fs = open(&path).unwrap();
+        assert_eq!(fs.volume_name(), "SMALL");
+        let root = fs.root_directory();
+        assert_eq!(fs.read_directory(root).unwrap().len(), 1);
+
+        // FATs covering more than the 32 bits sector numbers
+        let mut data = image.clone();
+        data[16] = 0xff;
+        LittleEndian::write_u32(&mut data[36..], u32::MAX);
+        assert!(matches!(
+            cluster_count(&mut io::Cursor::new(data)),
+            Err(FatError::InvalidBootRecord(_))
+        ));
+    }
+}
@@ -0,0 +1,268 @@
+use crate::error::FatError;
+use crate::fat32::{DirectoryEntry, EntryType, FAT32};
+use fuser::{
+    FileAttr, FileType, Filesystem, MountOption, ReplyAttr, ReplyData, ReplyDirectory, ReplyEntry,
+    Request,
+};
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::io::{Read, Seek};
+use std::path;
+use std::time::{Duration, SystemTime};
+
+// how long the kernel may keep entries and attributes,
+// nothing changes the volume while it is mounted
+const TTL: Duration = Duration::from_secs(1);
+
+// inode of the root directory, as expected by FUSE
+const ROOT_INODE: u64 = 1;
+
+impl<B: Read + Seek, M> FAT32<B, M> {
+    pub fn mount_fuse(&mut self, mountpoint: &path::Path) -> Result<(), FatError> {
+        // read only mount, blocks until the volume is unmounted
+        let options = [
+            MountOption::RO,
+            MountOption::FSName(self.volume_name().to_string()),
+            MountOption::Subtype("fat32".to_string()),
+        ];
+        fuser::mount2(FuseVolume::new(self), mountpoint, &options)?;
+        Ok(())
+    }
+}
+
+// an entry seen by lookup or readdir, `parent`
+// is the inode of the directory holding it
+struct Inode {
+    entry: EntryType,
+    parent: u64,
+    size: u32,
+    modified: SystemTime,
+}
+
+// FUSE view of a volume, inode n is inodes[n - 1]; write
+// operations keep the default ENOSYS implementations
+struct FuseVolume<'a, B, M> {
+    fs: &'a mut FAT32<B, M>,
+    inodes: Vec<Inode>,
+    // inode of each (parent inode, uppercase name)
+    names: BTreeMap<(u64, String), u64>,
+}
+
+impl<'a, B: Read + Seek, M> FuseVolume<'a, B, M> {
+    fn new(fs: &'a mut FAT32<B, M>) -> FuseVolume<'a, B, M> {
+        let root = Inode {
+            entry: EntryType::Dir(fs.root_directory()),
+            parent: ROOT_INODE,
+            size: 0,
+            modified: SystemTime::UNIX_EPOCH,
+        };
+        FuseVolume {
+            fs,
+            inodes: vec![root],
+            names: BTreeMap::new(),
+        }
+    }
+
+    fn inode(&self, ino: u64) -> Result<&Inode, FatError> {
+        self.inodes
+            .get((ino as usize).wrapping_sub(1))
+            .ok_or(FatError::NotFound)
+    }
+
+    fn add_inode(&mut self, parent: u64, entry: &DirectoryEntry) -> u64 {
+        // the same entry always gets the same inode
+        let key = (parent, entry_name(entry).to_ascii_uppercase());
+        if let Some(&ino) = self.names.get(&key) {
+            return ino;
+        }
+        self.inodes.push(Inode {
+            entry: entry.entry_type(),
+            parent,
+            size: entry.size(),
+            modified: entry.modified().into(),
+        });
+        let ino = self.inodes.len() as u64;
+        self.names.insert(key, ino);
+        ino
+    }
+
+    fn lookup_entry(&mut self, parent: u64, name: &str) -> Result<u64, FatError> {
+        let dir = match self.inode(parent)?.entry {
+            EntryType::Dir(dir) => dir,
+            EntryType::File(_) => return Err(FatError::NotADirectory),
+        };
+        let entry = self.fs.find_entry(dir, name)?.ok_or(FatError::NotFound)?;
+        Ok(self.add_inode(parent, &entry))
+    }
+
+    fn list(&mut self, ino: u64) -> Result<Vec<(u64, FileType, String)>, FatError> {
+        // `.` and `..` first, as FUSE expects them
+        let inode = self.inode(ino)?;
+        let (dir, parent) = match inode.entry {
+            EntryType::Dir(dir) => (dir, inode.parent),
+            EntryType::File(_) => return Err(FatError::NotADirectory),
+        };
+        let mut entries = vec![
+            (ino, FileType::Directory, ".".to_string()),
+            (parent, FileType::Directory, "..".to_string()),
+        ];
+        for entry in self.fs.read_directory_clean(dir)? {
+            let child = self.add_inode(ino, &entry);
+            let kind = match entry.entry_type() {
+                EntryType::Dir(_) => FileType::Directory,
+                EntryType::File(_) => FileType::RegularFile,
+            };
+            entries.push((child, kind, entry_name(&entry)));
+        }
+        Ok(entries)
+    }
+
+    fn attr(&self, ino: u64) -> Result<FileAttr, FatError> {
+        let inode = self.inode(ino)?;
+        let (kind, size, perm) = match inode.entry {
+            EntryType::Dir(_) => (FileType::Directory, 0, 0o555),
+            EntryType::File(_) => (FileType::RegularFile, inode.size as u64, 0o444),
+        };
+        Ok(FileAttr {
+            ino,
+            size,
+            blocks: size.div_ceil(512),
+            atime: inode.modified,
+            mtime: inode.modified,
+            ctime: inode.modified,
+            crtime: inode.modified,
+            kind,
+            perm,
+            nlink: 1,
+            uid: 0,
+            gid: 0,
+            rdev: 0,
+            blksize: self.fs.cluster_size_bytes(),
+            flags: 0,
+        })
+    }
+
+    fn read_data(&mut self, ino: u64, offset: u64, size: u32) -> Result<Vec<u8>, FatError> {
+        let file = match self.inode(ino)?.entry {
+            EntryType::File(file) => file,
+            EntryType::Dir(_) => return Err(FatError::IsDirectory),
+        };
+        let mut data = vec![0u8; size as usize];
+        let read = self.fs.read_file_at(file, offset, &mut data)?;
+        data.truncate(read);
+        Ok(data)
+    }
+}
+
+impl<B: Read + Seek, M> Filesystem for FuseVolume<'_, B, M> {
+    fn lookup(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        let result = match name.to_str() {
+            Some(name) => self.lookup_entry(parent, name),
+            None => Err(FatError::NotFound),
+        };
+        match result.and_then(|ino| self.attr(ino)) {
+            Ok(attr) => reply.entry(&TTL, &attr, 0),
+            Err(err) => reply.error(errno(&err)),
+        }
+    }
+
+    fn getattr(&mut self, _req: &Request<'_>, ino: u64, _fh: Option<u64>, reply: ReplyAttr) {
+        match self.attr(ino) {
+            Ok(attr) => reply.attr(&TTL, &attr),
+            Err(err) => reply.error(errno(&err)),
+        }
+    }
+
+    fn read(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        size: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyData,
+    ) {
+        if offset < 0 {
+            return reply.error(libc::EINVAL);
+        }
+        match self.read_data(ino, offset as u64, size) {
+            Ok(data) => reply.data(&data),
+            Err(err) => reply.error(errno(&err)),
+        }
+    }
+
+    fn readdir(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        mut reply: ReplyDirectory,
+    ) {
+        // `offset` is the position after the last entry returned
+        let entries = match self.list(ino) {
+            Ok(entries) => entries,
+            Err(err) => return reply.error(errno(&err)),
+        };
+        for (i, (ino, kind, name)) in entries.into_iter().enumerate().skip(offset as usize) {
+            if reply.add(ino, i as i64 + 1, kind, name) {
+                break;
+            }
+        }
+        reply.ok();
+    }
+}
+
+fn entry_name(entry: &DirectoryEntry) -> String {
+    // the long file name when there is one
+    entry
+        .long_name()
+        .map_or_else(|| entry.full_name(), String::from)
+}
+
+fn errno(err: &FatError) -> i32 {
+    match err {
+        FatError::NotFound => libc::ENOENT,
+        FatError::NotADirectory => libc::ENOTDIR,
+        FatError::IsDirectory => libc::EISDIR,
+        _ => libc::EIO,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+
+    #[test]
+    fn inode_table() {
+        let mut image = Vec::new();
+        let mut fs = FAT32::format(io::Cursor::new(&mut image), "FUSE", 64 << 20).unwrap();
+        let root = fs.root_directory();
+        let dir = fs.create_directory(root, "DIR").unwrap();
+        fs.write_file(dir, "A.TXT", b"hello world").unwrap();
+
+        let mut volume = FuseVolume::new(&mut fs);
+        let names = |entries: Vec<(u64, FileType, String)>| -> Vec<String> {
+            entries.into_iter().map(|(_, _, name)| name).collect()
+        };
+        assert_eq!(names(volume.list(ROOT_INODE).unwrap()), [".", "..", "DIR"]);
+        let dir = volume.lookup_entry(ROOT_INODE, "dir").unwrap();
+        assert_eq!(volume.lookup_entry(ROOT_INODE, "DIR").unwrap(), dir);
+        assert_eq!(volume.attr(dir).unwrap().kind, FileType::Directory);
+        assert_eq!(names(volume.list(dir).unwrap()), [".", "..", "A.TXT"]);
+        assert_eq!(volume.list(dir).unwrap()[1].0, ROOT_INODE);
+
+        let file = volume.lookup_entry(dir, "A.TXT").unwrap();
+        assert_eq!(volume.attr(file).unwrap().size, 11);
+        assert_eq!(volume.read_data(file, 6, 100).unwrap(), b"world");
+        assert!(matches!(
+            volume.lookup_entry(dir, "B.TXT"),
+            Err(FatError::NotFound)
+        ));
+        assert!(matches!(volume.list(file), Err(FatError::NotADirectory)));
+        assert!(volume.attr(100).is_err());
+    }
+}
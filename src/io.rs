@@ -0,0 +1,302 @@
+// I/O traits of the volume readers and writers: std::io with
+// the std feature, otherwise the subset below, so any block
+// device (e.g. an SD card driver) can back a volume
+#[cfg(feature = "std")]
+pub use std::io::{Cursor, Error, ErrorKind, Read, Result, Seek, SeekFrom, Write};
+
+#[cfg(not(feature = "std"))]
+pub use self::bare::*;
+
+#[cfg(not(feature = "std"))]
+mod bare {
+    #[cfg(feature = "alloc")]
+    use alloc::vec::Vec;
+    use core::{cmp, error, fmt, result};
+
+    pub type Result<T> = result::Result<T, Error>;
+
+    // the std::io::ErrorKind values used by the crate
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    #[non_exhaustive]
+    pub enum ErrorKind {
+        InvalidInput,
+        InvalidData,
+        UnexpectedEof,
+        WriteZero,
+        PermissionDenied,
+        Interrupted,
+        Other,
+    }
+
+    impl ErrorKind {
+        fn as_str(&self) -> &'static str {
+            match self {
+                ErrorKind::InvalidInput => "invalid input parameter",
+                ErrorKind::InvalidData => "invalid data",
+                ErrorKind::UnexpectedEof => "unexpected end of file",
+                ErrorKind::WriteZero => "write zero",
+                ErrorKind::PermissionDenied => "permission denied",
+                ErrorKind::Interrupted => "operation interrupted",
+                ErrorKind::Other => "other error",
+            }
+        }
+    }
+
+    // error of a block device, without allocation
+    // the message can only be a static string
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct Error {
+        kind: ErrorKind,
+        message: Option<&'static str>,
+    }
+
+    impl Error {
+        pub fn new(kind: ErrorKind, message: &'static str) -> Error {
+            Error {
+                kind,
+                message: Some(message),
+            }
+        }
+
+        pub fn kind(&self) -> ErrorKind {
+            self.kind
+        }
+    }
+
+    impl From<ErrorKind> for Error {
+        fn from(kind: ErrorKind) -> Error {
+            Error {
+                kind,
+                message: None,
+            }
+        }
+    }
+
+    impl fmt::Display for Error {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str(self.message.unwrap_or_else(|| self.kind.as_str()))
+        }
+    }
+
+    impl error::Error for Error {}
+
+    pub trait Read {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize>;
+
+        fn read_exact(&mut self, mut buf: &mut [u8]) -> Result<()> {
+            while !buf.is_empty() {
+                match self.read(buf) {
+                    Ok(0) => {
+                        return Err(Error::new(
+                            ErrorKind::UnexpectedEof,
+                            "failed to fill whole buffer",
+                        ))
+                    }
+                    Ok(n) => buf = &mut buf[n..],
+                    Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                    Err(err) => return Err(err),
+                }
+            }
+            Ok(())
+        }
+    }
+
+    pub trait Write {
+        fn write(&mut self, buf: &[u8]) -> Result<usize>;
+
+        fn flush(&mut self) -> Result<()>;
+
+        fn write_all(&mut self, mut buf: &[u8]) -> Result<()> {
+            while !buf.is_empty() {
+                match self.write(buf) {
+                    Ok(0) => {
+                        return Err(Error::new(
+                            ErrorKind::WriteZero,
+                            "failed to write whole buffer",
+                        ))
+                    }
+                    Ok(n) => buf = &buf[n..],
+                    Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                    Err(err) => return Err(err),
+                }
+            }
+            Ok(())
+        }
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum SeekFrom {
+        Start(u64),
+        End(i64),
+        Current(i64),
+    }
+
+    pub trait Seek {
+        fn seek(&mut self, pos: SeekFrom) -> Result<u64>;
+    }
+
+    impl<R: Read + ?Sized> Read for &mut R {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+            (**self).read(buf)
+        }
+    }
+
+    impl<W: Write + ?Sized> Write for &mut W {
+        fn write(&mut self, buf: &[u8]) -> Result<usize> {
+            (**self).write(buf)
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            (**self).flush()
+        }
+    }
+
+    impl<S: Seek + ?Sized> Seek for &mut S {
+        fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+            (**self).seek(pos)
+        }
+    }
+
+    // image held in memory, as std::io::Cursor
+    #[derive(Clone, Debug, Default)]
+    pub struct Cursor<T> {
+        inner: T,
+        pos: u64,
+    }
+
+    impl<T> Cursor<T> {
+        pub fn new(inner: T) -> Cursor<T> {
+            Cursor { inner, pos: 0 }
+        }
+
+        pub fn into_inner(self) -> T {
+            self.inner
+        }
+
+        pub fn get_ref(&self) -> &T {
+            &self.inner
+        }
+
+        pub fn get_mut(&mut self) -> &mut T {
+            &mut self.inner
+        }
+
+        pub fn position(&self) -> u64 {
+            self.pos
+        }
+
+        pub fn set_position(&mut self, pos: u64) {
+            self.pos = pos;
+        }
+    }
+
+    impl<T: AsRef<[u8]>> Read for Cursor<T> {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+            let data = self.inner.as_ref();
+            let start = cmp::min(self.pos, data.len() as u64) as usize;
+            let n = cmp::min(buf.len(), data.len() - start);
+            buf[..n].copy_from_slice(&data[start..][..n]);
+            self.pos += n as u64;
+            Ok(n)
+        }
+    }
+
+    impl<T: AsRef<[u8]>> Seek for Cursor<T> {
+        fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+            let pos = match pos {
+                SeekFrom::Start(n) => Some(n),
+                SeekFrom::End(n) => (self.inner.as_ref().len() as u64).checked_add_signed(n),
+                SeekFrom::Current(n) => self.pos.checked_add_signed(n),
+            };
+            self.pos =
+                pos.ok_or_else(|| Error::new(ErrorKind::InvalidInput, "invalid seek position"))?;
+            Ok(self.pos)
+        }
+    }
+
+    impl Write for Cursor<&mut [u8]> {
+        fn write(&mut self, buf: &[u8]) -> Result<usize> {
+            // fixed size, as a block device
+            let start = cmp::min(self.pos, self.inner.len() as u64) as usize;
+            let n = cmp::min(buf.len(), self.inner.len() - start);
+            self.inner[start..][..n].copy_from_slice(&buf[..n]);
+            self.pos += n as u64;
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[cfg(feature = "alloc")]
+    fn write_vec(vec: &mut Vec<u8>, pos: &mut u64, buf: &[u8]) -> Result<usize> {
+        // grows the vector, with zeros up to `pos`
+        use core::convert::TryFrom;
+
+        let start = usize::try_from(*pos)
+            .map_err(|_| Error::new(ErrorKind::InvalidInput, "cursor position too large"))?;
+        let end = start + buf.len();
+        if vec.len() < end {
+            vec.resize(end, 0);
+        }
+        vec[start..end].copy_from_slice(buf);
+        *pos = end as u64;
+        Ok(buf.len())
+    }
+
+    #[cfg(feature = "alloc")]
+    impl Write for Cursor<Vec<u8>> {
+        fn write(&mut self, buf: &[u8]) -> Result<usize> {
+            write_vec(&mut self.inner, &mut self.pos, buf)
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[cfg(feature = "alloc")]
+    impl Write for Cursor<&mut Vec<u8>> {
+        fn write(&mut self, buf: &[u8]) -> Result<usize> {
+            write_vec(self.inner, &mut self.pos, buf)
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod tests {
+    use super::*;
+    use crate::fat32::{EntryType, FAT32};
+
+    #[test]
+    fn fixed_size_devices() {
+        // a volume on a slice, as on a block device
+        // that can't grow
+        let mut device = vec![0u8; 33 << 20];
+        let mut fs = FAT32::format(Cursor::new(&mut device[..]), "DEV", 33 << 20).unwrap();
+        let root = fs.root_directory();
+        fs.write_file(root, "A.TXT", b"hello").unwrap();
+        fs.flush().unwrap();
+        drop(fs);
+
+        let mut fs = FAT32::from_bytes(&device).unwrap();
+        match fs.open_path("/A.TXT").unwrap() {
+            EntryType::File(file) => assert_eq!(fs.read_file(file).unwrap(), b"hello"),
+            EntryType::Dir(_) => panic!("A.TXT is a directory"),
+        }
+
+        let mut cursor = Cursor::new(&device[..]);
+        cursor.seek(SeekFrom::End(-2)).unwrap();
+        let err = cursor.read_exact(&mut [0u8; 4]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+        let mut end = [0u8; 1];
+        let mut cursor = Cursor::new(&mut end[..]);
+        assert_eq!(cursor.write(b"ab").unwrap(), 1);
+        assert!(cursor.seek(SeekFrom::Current(-2)).is_err());
+    }
+}
@@ -0,0 +1,47 @@
+// the volume readers and writers only need alloc and a
+// crate::io block device, std adds the host files, stdin
+// and SystemTime; unit tests link std either way
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
+// messages of the log crate, dropped without the log feature
+#[allow(unused_macros)]
+macro_rules! debug {
+    ($($arg:tt)*) => {{
+        #[cfg(feature = "log")]
+        log::debug!($($arg)*);
+        #[cfg(not(feature = "log"))]
+        let _ = format_args!($($arg)*);
+    }};
+}
+
+#[allow(unused_macros)]
+macro_rules! warn {
+    ($($arg:tt)*) => {{
+        #[cfg(feature = "log")]
+        log::warn!($($arg)*);
+        #[cfg(not(feature = "log"))]
+        let _ = format_args!($($arg)*);
+    }};
+}
+
+#[cfg(feature = "tokio")]
+pub mod async_fat32;
+pub mod attributes;
+#[cfg(feature = "alloc")]
+pub mod builder;
+pub mod error;
+#[cfg(feature = "alloc")]
+pub mod fat16;
+#[cfg(feature = "alloc")]
+pub mod fat32;
+#[cfg(feature = "alloc")]
+pub mod filesystem;
+#[cfg(feature = "fuse")]
+pub mod fuse;
+pub mod io;
+pub mod timestamp;
+#[cfg(feature = "wasm")]
+pub mod wasm;
@@ -1,35 +1,26 @@
-mod fat32;
-use fat32::*;
+use fat_rs::error::FatError;
+use fat_rs::filesystem::{self, Directory, FatFilesystem};
 
-// recursively browse `dir` in `fs` and displays every element found
-// pfx is used to display the whole path of every element
-fn browse_dir(pfx: String, fs: &mut FAT32, dir: Directory) {
-    let entries = fs.read_directory(dir).unwrap();
-
-    for entry in entries.iter() {
+fn browse_dir(fs: &mut dyn FatFilesystem, dir: Directory, prefix: &str) -> Result<(), FatError> {
+    // display the whole path of every element,
+    // whatever the FAT type of the volume
+    for entry in fs.read_directory(dir)? {
         let name = entry.full_name();
-        let path = format!("{}/{}", pfx, name);
+        if name == "." || name == ".." {
+            continue;
+        }
+        let path = format!("{}/{}", prefix, name);
         println!("{}", path);
-
-        match entry.entry_type() {
-            EntryType::Dir(dir) => {
-                // ignore . and .. to avoid
-                // infinite recursion
-                if !name.starts_with('.') {
-                    // recursively descend into
-                    // sub directories
-                    browse_dir(path, fs, dir);
-                }
-            }
-
-            EntryType::File(_f) => (),
+        if let Some(dir) = entry.directory() {
+            browse_dir(fs, dir, &path)?;
         }
     }
+    Ok(())
 }
 
 fn main() {
     let path = std::path::Path::new("imgs/fat32.img");
-    let mut fs = FAT32::new(path).unwrap();
+    let mut fs = filesystem::open(path).unwrap();
     println!(
         "FAT volume label {}, number of sectors {:x}, size {:x}",
         fs.volume_name(),
@@ -38,5 +29,5 @@ fn main() {
     );
 
     let root = fs.root_directory();
-    browse_dir("".to_string(), &mut fs, root);
+    browse_dir(fs.as_mut(), root, "").unwrap();
 }
@@ -0,0 +1,193 @@
+#[cfg(feature = "serde")]
+use alloc::{format, string::String};
+#[cfg(feature = "std")]
+use std::time::{Duration, SystemTime};
+
+// date and time as stored in directory entries,
+// FAT does not record any time zone
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Timestamp {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+    pub centisecond: u8,
+}
+
+impl Timestamp {
+    pub fn from_fat_date_time(date: u16, time: u16, tenths: u8) -> Timestamp {
+        // date: bits 9-15 year since 1980, 5-8 month, 0-4 day
+        // time: bits 11-15 hours, 5-10 minutes, 0-4 seconds / 2
+        // tenths: 10 ms units (0-199) to add to the time
+        Timestamp {
+            year: 1980 + (date >> 9),
+            month: ((date >> 5) & 0xf) as u8,
+            day: (date & 0x1f) as u8,
+            hour: (time >> 11) as u8,
+            minute: ((time >> 5) & 0x3f) as u8,
+            second: ((time & 0x1f) * 2) as u8 + tenths / 100,
+            centisecond: tenths % 100,
+        }
+    }
+
+    pub fn to_fat_date_time(&self) -> (u16, u16, u8) {
+        // inverse of from_fat_date_time
+        let date =
+            (self.year.saturating_sub(1980) << 9) | ((self.month as u16) << 5) | self.day as u16;
+        let time =
+            ((self.hour as u16) << 11) | ((self.minute as u16) << 5) | (self.second as u16 / 2);
+        let tenths = (self.second % 2) * 100 + self.centisecond;
+        (date, time, tenths)
+    }
+
+    pub fn fits_fat(&self) -> bool {
+        // whether to_fat_date_time can store it as is,
+        // years go from 1980 to 2107 on 7 bits
+        (1980..=2107).contains(&self.year)
+            && (1..=12).contains(&self.month)
+            && (1..=31).contains(&self.day)
+            && self.hour < 24
+            && self.minute < 60
+            && self.second < 60
+            && self.centisecond < 100
+    }
+
+    #[cfg(feature = "std")]
+    fn days_since_epoch(&self) -> i64 {
+        // days from 1970-01-01 in the proleptic gregorian
+        // calendar, unset month and day (0) count as 1;
+        // negative before 1970, e.g. for a zeroed year
+        let month = self.month.max(1) as i64;
+        let day = self.day.max(1) as i64;
+        let year = self.year as i64 - (month <= 2) as i64;
+        let era = year.div_euclid(400);
+        let year_of_era = year - era * 400;
+        let shifted_month = if month > 2 { month - 3 } else { month + 9 };
+        let day_of_year = (153 * shifted_month + 2) / 5 + day - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        era * 146097 + day_of_era - 719468
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<Timestamp> for SystemTime {
+    fn from(ts: Timestamp) -> SystemTime {
+        // timestamps are taken as UTC, and clamped
+        // to the epoch when they are before it
+        let seconds = ts.days_since_epoch() * 86400
+            + ts.hour as i64 * 3600
+            + ts.minute as i64 * 60
+            + ts.second as i64;
+        if seconds < 0 {
+            return SystemTime::UNIX_EPOCH;
+        }
+        SystemTime::UNIX_EPOCH
+            + Duration::from_secs(seconds as u64)
+            + Duration::from_millis(ts.centisecond as u64 * 10)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Timestamp {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        // ISO 8601, without time zone since FAT has none
+        serializer.collect_str(&format_args!(
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second, self.centisecond
+        ))
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Timestamp {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Timestamp, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        parse_iso8601(&text)
+            .ok_or_else(|| serde::de::Error::custom(format!("invalid timestamp: {}", text)))
+    }
+}
+
+#[cfg(feature = "serde")]
+fn parse_iso8601(text: &str) -> Option<Timestamp> {
+    // only the form written by serialize:
+    // YYYY-MM-DDTHH:MM:SS.cc
+    let bytes = text.as_bytes();
+    let separators = [
+        (4, b'-'),
+        (7, b'-'),
+        (10, b'T'),
+        (13, b':'),
+        (16, b':'),
+        (19, b'.'),
+    ];
+    if bytes.len() != 22 || separators.iter().any(|&(i, c)| bytes[i] != c) {
+        return None;
+    }
+    Some(Timestamp {
+        year: text.get(0..4)?.parse().ok()?,
+        month: text.get(5..7)?.parse().ok()?,
+        day: text.get(8..10)?.parse().ok()?,
+        hour: text.get(11..13)?.parse().ok()?,
+        minute: text.get(14..16)?.parse().ok()?,
+        second: text.get(17..19)?.parse().ok()?,
+        centisecond: text.get(20..22)?.parse().ok()?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fat_date_time() {
+        // 2021-03-14 15:09:27.65
+        let date = (41 << 9) | (3 << 5) | 14;
+        let time = (15 << 11) | (9 << 5) | 13;
+        let ts = Timestamp::from_fat_date_time(date, time, 165);
+        assert_eq!((ts.year, ts.month, ts.day), (2021, 3, 14));
+        assert_eq!(
+            (ts.hour, ts.minute, ts.second, ts.centisecond),
+            (15, 9, 27, 65)
+        );
+        assert_eq!(ts.to_fat_date_time(), (date, time, 165));
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn system_time() {
+        let date = (41 << 9) | (3 << 5) | 14;
+        let time = (15 << 11) | (9 << 5) | 13;
+        let ts = Timestamp::from_fat_date_time(date, time, 165);
+        let since_epoch = SystemTime::from(ts)
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap();
+        assert_eq!(since_epoch, Duration::from_millis(1615734567650));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn zeroed_dates() {
+        // a zeroed on-disk date is 1980-01-01
+        let ts = Timestamp::from_fat_date_time(0, 0, 0);
+        let since_epoch = SystemTime::from(ts)
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap();
+        assert_eq!(since_epoch, Duration::from_secs(315532800));
+        // a zeroed year is before the epoch
+        let ts = Timestamp { year: 0, ..ts };
+        assert_eq!(SystemTime::from(ts), SystemTime::UNIX_EPOCH);
+    }
+
+    #[test]
+    fn fat_ranges() {
+        let ts = Timestamp::from_fat_date_time((41 << 9) | (3 << 5) | 14, 0, 0);
+        assert!(ts.fits_fat());
+        assert!(!Timestamp { month: 13, ..ts }.fits_fat());
+        assert!(!Timestamp { day: 0, ..ts }.fits_fat());
+        assert!(!Timestamp { year: 1979, ..ts }.fits_fat());
+        assert!(!Timestamp { year: 2108, ..ts }.fits_fat());
+        assert!(!Timestamp { hour: 24, ..ts }.fits_fat());
+    }
+}
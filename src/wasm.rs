@@ -0,0 +1,73 @@
+use std::io::{self, Read, Seek};
+
+// image loaded in the browser, e.g. from the arrayBuffer() of a
+// File object, copied once to wasm memory so that FAT32 can read
+// it like any other seekable image
+#[derive(Debug)]
+pub struct WasmBackend {
+    data: io::Cursor<Vec<u8>>,
+}
+
+impl WasmBackend {
+    pub fn new(array: &js_sys::Uint8Array) -> WasmBackend {
+        WasmBackend::from(array.to_vec())
+    }
+}
+
+impl From<Vec<u8>> for WasmBackend {
+    fn from(data: Vec<u8>) -> WasmBackend {
+        WasmBackend {
+            data: io::Cursor::new(data),
+        }
+    }
+}
+
+impl Read for WasmBackend {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.data.read(buf)
+    }
+}
+
+impl Seek for WasmBackend {
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        self.data.seek(pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fat32::FAT32;
+
+    #[test]
+    fn open_wasm_backend() {
+        let mut image = Vec::new();
+        FAT32::format(io::Cursor::new(&mut image), "BROWSER", 64 << 20).unwrap();
+        let mut fs = FAT32::from_reader(WasmBackend::from(image)).unwrap();
+        assert_eq!(fs.volume_name(), "BROWSER");
+        let root = fs.root_directory();
+        assert!(fs.read_directory(root).unwrap().is_empty());
+    }
+
+    // run in node by wasm-pack test --node, with the
+    // image in a Uint8Array as it comes from the browser
+    #[cfg(target_arch = "wasm32")]
+    #[wasm_bindgen_test::wasm_bindgen_test]
+    fn open_uint8_array() {
+        let mut image = Vec::new();
+        let mut fs = FAT32::format(io::Cursor::new(&mut image), "BROWSER", 33 << 20).unwrap();
+        let root = fs.root_directory();
+        fs.write_file(root, "A.TXT", b"from js").unwrap();
+        fs.flush().unwrap();
+        drop(fs);
+
+        let array = js_sys::Uint8Array::from(image.as_slice());
+        let mut fs = FAT32::from_reader(WasmBackend::new(&array)).unwrap();
+        assert_eq!(fs.volume_name(), "BROWSER");
+        let file = match fs.open_path("/A.TXT").unwrap() {
+            crate::fat32::EntryType::File(file) => file,
+            crate::fat32::EntryType::Dir(_) => panic!("A.TXT is a directory"),
+        };
+        assert_eq!(fs.read_file(file).unwrap(), b"from js");
+    }
+}
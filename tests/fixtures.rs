@@ -0,0 +1,138 @@
+// the images are files of the host,
+// and fatfs is not a wasm32 dev-dependency
+#![cfg(all(feature = "std", not(target_arch = "wasm32")))]
+
+use fat_rs::fat32::{EntryType, FAT32};
+use fat_rs::filesystem;
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+// images formatted and filled by the fatfs crate, an independent
+// FAT implementation, written to `fat-rs-fixture-<name>.img`
+// in the temporary directory
+
+fn format(size: u64) -> io::Cursor<Vec<u8>> {
+    // FAT32 volume labelled "TEST", with 512 bytes clusters
+    let mut image = io::Cursor::new(vec![0u8; size as usize]);
+    let options = fatfs::FormatVolumeOptions::new()
+        .fat_type(fatfs::FatType::Fat32)
+        .bytes_per_cluster(512)
+        .fats(2)
+        .volume_id(0x1234_ABCD)
+        .volume_label(*b"TEST       ");
+    fatfs::format_volume(&mut image, options).unwrap();
+    image
+}
+
+fn save(name: &str, image: io::Cursor<Vec<u8>>) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("fat-rs-fixture-{}.img", name));
+    std::fs::write(&path, image.into_inner()).unwrap();
+    path
+}
+
+fn simple(name: &str, size: u64) -> PathBuf {
+    save(name, format(size))
+}
+
+fn with_files(name: &str, size: u64) -> PathBuf {
+    let mut image = format(size);
+    let fs = fatfs::FileSystem::new(&mut image, fatfs::FsOptions::new()).unwrap();
+    {
+        let root = fs.root_dir();
+        let mut hello = root.create_file("HELLO.TXT").unwrap();
+        hello.write_all(b"Hello, world!\n").unwrap();
+        let data: Vec<u8> = (0..3000u32).map(|i| (i % 251) as u8).collect();
+        root.create_file("DATA.BIN")
+            .unwrap()
+            .write_all(&data)
+            .unwrap();
+        root.create_file("EMPTY").unwrap();
+        let docs = root.create_dir("DOCS").unwrap();
+        let mut notes = docs.create_file("Release notes.txt").unwrap();
+        notes.write_all(b"FAT32 notes\n").unwrap();
+    }
+    fs.unmount().unwrap();
+    save(name, image)
+}
+
+#[test]
+fn simple_image() {
+    let mut fs = FAT32::new(&simple("simple", 34 << 20)).unwrap();
+    assert_eq!(fs.volume_name(), "TEST");
+    assert_eq!(fs.sector_count(), 34 << 11);
+    assert_eq!(fs.volume_size(), 34 << 20);
+    assert!(fs.total_cluster_count() >= 65525);
+    assert_eq!(fs.volume_label_from_root_dir().unwrap().unwrap(), "TEST");
+    fs.validate_boot_sector().unwrap();
+    assert_eq!(fs.backup_boot_sector().unwrap(), *fs.boot_sector());
+
+    let root = fs.root_directory();
+    assert!(fs.read_directory(root).unwrap().is_empty());
+    assert_eq!(
+        fs.free_cluster_count().unwrap(),
+        fs.total_cluster_count() - 1
+    );
+}
+
+#[test]
+fn image_with_files() {
+    let mut fs = FAT32::new(&with_files("with-files", 34 << 20)).unwrap();
+    let root = fs.root_directory();
+    let entries: Vec<(String, u32)> = fs
+        .read_directory(root)
+        .unwrap()
+        .iter()
+        .map(|entry| (entry.full_name(), entry.size()))
+        .collect();
+    assert_eq!(
+        entries,
+        [
+            ("HELLO.TXT".to_string(), 14),
+            ("DATA.BIN".to_string(), 3000),
+            ("EMPTY".to_string(), 0),
+            ("DOCS".to_string(), 0),
+        ]
+    );
+
+    let hello = match fs.open_path("/HELLO.TXT").unwrap() {
+        EntryType::File(file) => file,
+        EntryType::Dir(_) => panic!("HELLO.TXT is a directory"),
+    };
+    assert_eq!(fs.read_file(hello).unwrap(), b"Hello, world!\n");
+    let data = match fs.open_path("/DATA.BIN").unwrap() {
+        EntryType::File(file) => file,
+        EntryType::Dir(_) => panic!("DATA.BIN is a directory"),
+    };
+    let expected: Vec<u8> = (0..3000u32).map(|i| (i % 251) as u8).collect();
+    assert_eq!(fs.read_file(data).unwrap(), expected);
+
+    // long file name in a sub directory
+    let notes = fs.stat("/docs/release notes.txt").unwrap();
+    assert_eq!(notes.name, "RELEAS~1.TXT");
+    assert_eq!(notes.long_name.as_deref(), Some("Release notes.txt"));
+    assert_eq!(notes.size, 12);
+    assert!(fs.check_integrity().unwrap().is_empty());
+}
+
+#[test]
+fn open_detects_fat_type() {
+    // fatfs can't make volumes of 1 MiB FAT32, it falls back to
+    // FAT12, read through the fat16 module
+    let images = [
+        (simple("open-simple", 34 << 20), false),
+        (with_files("open-files", 34 << 20), true),
+        (with_files("open-small", 1 << 20), true),
+    ];
+    for (path, has_files) in images {
+        let mut fs = filesystem::open(&path).unwrap();
+        assert_eq!(fs.volume_name(), "TEST");
+        let root = fs.root_directory();
+        let names: Vec<String> = fs
+            .read_directory(root)
+            .unwrap()
+            .iter()
+            .map(|entry| entry.full_name())
+            .collect();
+        assert_eq!(names.contains(&"HELLO.TXT".to_string()), has_files);
+    }
+}